@@ -0,0 +1,198 @@
+// Tagger throughput on a synthetic corpus, in tokens per second
+// cargo bench --bench tagger
+use std::time::Instant;
+use yogurt::decoder::Decoder;
+use yogurt::features::Template;
+use yogurt::lexemizer::Case;
+use yogurt::lexemizer::Lexeme;
+use yogurt::tokenizer::Hashing;
+use yogurt::tokenizer::PartOfSpeech;
+use yogurt::tokenizer::PartOfSpeech::*;
+use yogurt::tokenizer::Tagger;
+use yogurt::tokenizer::TrainOptions;
+
+const TRAIN_SENTENCES: usize = 20000;
+const TEST_SENTENCES: usize = 20000;
+const EPOCHS: usize = 5;
+
+fn main() {
+    let mut rng = Rng(7);
+    let words = Words::new(&mut rng);
+    let train: Vec<_> = (0..TRAIN_SENTENCES)
+        .map(|_| sentence(&mut rng, &words))
+        .collect();
+    let test: Vec<_> = (0..TEST_SENTENCES)
+        .map(|_| sentence(&mut rng, &words))
+        .collect();
+
+    let options = TrainOptions {
+        epochs: EPOCHS,
+        ..TrainOptions::default()
+    };
+    bench("interned", &train, &test, &options);
+    let hashed = TrainOptions {
+        hashing: Some(Hashing { bits: 18, seed: 0 }),
+        ..options.clone()
+    };
+    bench("hashed", &train, &test, &hashed);
+    let beam = TrainOptions {
+        decoder: Decoder::Beam(4),
+        ..options.clone()
+    };
+    bench("beam", &train, &test, &beam);
+    let viterbi = TrainOptions {
+        decoder: Decoder::Viterbi,
+        templates: Template::groups(&[
+            Template::WORDS,
+            Template::AFFIXES,
+            Template::ORTHOGRAPHY,
+            Template::CONJUNCTIONS,
+            &[Template::Tag(-1)],
+        ]),
+        ..options.clone()
+    };
+    bench("viterbi", &train, &test, &viterbi);
+    let parallel = TrainOptions {
+        threads: 4,
+        ..options.clone()
+    };
+    bench("parallel", &train, &test, &parallel);
+}
+
+fn bench(
+    name: &str,
+    train: &[Vec<(Lexeme, PartOfSpeech)>],
+    test: &[Vec<(Lexeme, PartOfSpeech)>],
+    options: &TrainOptions,
+) {
+    println!("{}:", name);
+    let tokens: usize = train.iter().map(Vec::len).sum::<usize>() * EPOCHS;
+    let start = Instant::now();
+    let tagger = Tagger::train(train, options).unwrap();
+    report("train", tokens, start);
+
+    let lexemes: Vec<Vec<Lexeme>> = test
+        .iter()
+        .map(|sentence| sentence.iter().map(|(lexeme, _)| lexeme.clone()).collect())
+        .collect();
+    let tokens: usize = lexemes.iter().map(Vec::len).sum();
+    let start = Instant::now();
+    let mut correct = 0;
+    for (sentence, gold) in lexemes.iter().zip(test.iter()) {
+        let tags = tagger.tag(sentence);
+        correct += tags
+            .iter()
+            .zip(gold)
+            .filter(|(tag, (_, gold))| *tag == gold)
+            .count();
+    }
+    report("tag", tokens, start);
+    println!("  accuracy: {:.2}%", correct as f64 * 100.0 / tokens as f64);
+}
+
+fn report(name: &str, tokens: usize, start: Instant) {
+    let seconds = start.elapsed().as_secs_f64();
+    println!(
+        "  {}: {} tokens in {:.2}s, {:.0} tokens/s",
+        name,
+        tokens,
+        seconds,
+        tokens as f64 / seconds
+    );
+}
+
+// Made up words for each open class, so there are plenty of affixes to learn
+struct Words {
+    nouns: Vec<String>,
+    verbs: Vec<String>,
+    adjectives: Vec<String>,
+}
+
+impl Words {
+    fn new(rng: &mut Rng) -> Words {
+        let stem = |rng: &mut Rng| -> String {
+            const SYLLABLES: &[&str] = &[
+                "ka", "lo", "mi", "ren", "sta", "bor", "vel", "qui", "dra", "po", "zen", "tal",
+            ];
+            (0..2 + rng.below(2))
+                .map(|_| SYLLABLES[rng.below(SYLLABLES.len())])
+                .collect()
+        };
+        let nouns: Vec<String> = (0..2000).map(|_| stem(rng)).collect();
+        Words {
+            // half the verbs are also nouns
+            verbs: (0..1000)
+                .map(|i| {
+                    if i % 2 == 0 {
+                        nouns[i].clone()
+                    } else {
+                        stem(rng)
+                    }
+                })
+                .collect(),
+            adjectives: (0..1000).map(|_| stem(rng) + "ous").collect(),
+            nouns,
+        }
+    }
+}
+
+fn sentence(rng: &mut Rng, words: &Words) -> Vec<(Lexeme, PartOfSpeech)> {
+    let mut tagged: Vec<(String, PartOfSpeech)> = Vec::new();
+    let noun_phrase = |rng: &mut Rng, tagged: &mut Vec<(String, PartOfSpeech)>| {
+        let determiner = ["the", "a", "this", "every"][rng.below(4)];
+        tagged.push((determiner.to_string(), DT));
+        if rng.below(2) == 0 {
+            tagged.push((words.adjectives[rng.below(1000)].clone(), JJ));
+        }
+        let noun = &words.nouns[rng.below(2000)];
+        if rng.below(3) == 0 {
+            tagged.push((noun.clone() + "s", NNS));
+        } else {
+            tagged.push((noun.clone(), NN));
+        }
+    };
+    noun_phrase(rng, &mut tagged);
+    let verb = &words.verbs[rng.below(1000)];
+    match rng.below(3) {
+        0 => tagged.push((verb.clone() + "s", VBZ)),
+        1 => tagged.push((verb.clone() + "ed", VBD)),
+        _ => {
+            tagged.push(("will".to_string(), MD));
+            tagged.push((verb.clone(), VB));
+        }
+    }
+    if rng.below(2) == 0 {
+        noun_phrase(rng, &mut tagged);
+    }
+    if rng.below(2) == 0 {
+        tagged.push((["in", "on", "with"][rng.below(3)].to_string(), IN));
+        noun_phrase(rng, &mut tagged);
+    }
+    tagged.push((".".to_string(), ENDPUNC));
+
+    let mut start = 0;
+    tagged
+        .into_iter()
+        .map(|(word, tag)| {
+            let lexeme = Lexeme {
+                start,
+                end: start + word.len(),
+                value: word.into_bytes(),
+                case: Case::Lower,
+            };
+            start = lexeme.end + 1;
+            (lexeme, tag)
+        })
+        .collect()
+}
+
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
@@ -0,0 +1,185 @@
+use super::conllu;
+use super::lexemizer::Lexeme;
+
+// Alignment between lexemes and the gold tokens of a treebank, by their spans in the same text
+// The lexemizer and treebanks split differently ("don't" is "do" "not" for us, "do" "n't" in UD)
+// and the lexemizer rewrites values ("colour" to "color"), so values aren't compared at all.
+//
+// Lexemes and gold tokens that overlap, directly or through each other, form a group.
+// A group with as many lexemes as gold tokens is aligned one-to-one in order if their spans
+// are the same, if the lexemes all share a span (a special expansion), or if the gold tokens
+// all share a span (a multiword token). Any other group aligns each lexeme with the gold
+// tokens it overlaps
+#[derive(Debug, Clone)]
+pub struct Alignment {
+    pub lexeme_to_gold: Vec<Vec<usize>>,
+    pub gold_to_lexeme: Vec<Vec<usize>>,
+}
+
+impl Alignment {
+    // Spans must be in order of their start, as the lexemizer and the CoNLL-U reader give them
+    pub fn new<V>(lexemes: &[Lexeme<V>], gold: &[(usize, usize)]) -> Alignment {
+        let mut overlaps = vec![Vec::new(); lexemes.len()];
+        let mut first = 0;
+        for (i, lexeme) in lexemes.iter().enumerate() {
+            while first < gold.len() && gold[first].1 <= lexeme.start {
+                first += 1;
+            }
+            let mut j = first;
+            while j < gold.len() && gold[j].0 < lexeme.end {
+                if gold[j].1 > lexeme.start {
+                    overlaps[i].push(j);
+                }
+                j += 1;
+            }
+        }
+
+        // gold tokens are numbered after the lexemes
+        let mut groups = Groups::new(lexemes.len() + gold.len());
+        for (i, overlap) in overlaps.iter().enumerate() {
+            for j in overlap {
+                groups.union(i, lexemes.len() + j);
+            }
+        }
+        let mut members: Vec<(Vec<usize>, Vec<usize>)> =
+            vec![(Vec::new(), Vec::new()); lexemes.len() + gold.len()];
+        for i in 0..lexemes.len() {
+            members[groups.find(i)].0.push(i);
+        }
+        for j in 0..gold.len() {
+            members[groups.find(lexemes.len() + j)].1.push(j);
+        }
+
+        let mut lexeme_to_gold = vec![Vec::new(); lexemes.len()];
+        let mut gold_to_lexeme = vec![Vec::new(); gold.len()];
+        for (group_lexemes, group_gold) in members.iter() {
+            let lexeme_span = |i: &usize| (lexemes[*i].start, lexemes[*i].end);
+            let in_order = group_lexemes.len() == group_gold.len()
+                && (group_lexemes
+                    .iter()
+                    .zip(group_gold)
+                    .all(|(i, j)| lexeme_span(i) == gold[*j])
+                    || group_lexemes
+                        .iter()
+                        .all(|i| lexeme_span(i) == lexeme_span(&group_lexemes[0]))
+                    || group_gold.iter().all(|j| gold[*j] == gold[group_gold[0]]));
+            if in_order {
+                for (i, j) in group_lexemes.iter().zip(group_gold) {
+                    lexeme_to_gold[*i].push(*j);
+                    gold_to_lexeme[*j].push(*i);
+                }
+            } else {
+                for i in group_lexemes {
+                    for j in overlaps[*i].iter() {
+                        lexeme_to_gold[*i].push(*j);
+                        gold_to_lexeme[*j].push(*i);
+                    }
+                }
+            }
+        }
+        Alignment {
+            lexeme_to_gold,
+            gold_to_lexeme,
+        }
+    }
+
+    pub fn conllu<V>(lexemes: &[Lexeme<V>], sentence: &conllu::Sentence) -> Alignment {
+        let gold: Vec<(usize, usize)> = sentence
+            .words
+            .iter()
+            .map(|word| (word.start, word.end))
+            .collect();
+        Alignment::new(lexemes, &gold)
+    }
+
+    // The gold token of a lexeme, if they are aligned one-to-one
+    pub fn one_to_one(&self, lexeme: usize) -> Option<usize> {
+        match self.lexeme_to_gold[lexeme][..] {
+            [gold] if self.gold_to_lexeme[gold].len() == 1 => Some(gold),
+            _ => None,
+        }
+    }
+
+    // Gold tags for each lexeme. Lexemes that are part of a gold token take its tag,
+    // lexemes that cover several gold tokens take the tag of the last one,
+    // and lexemes that cover none get None
+    pub fn project<T: Copy>(&self, tags: &[T]) -> Vec<Option<T>> {
+        self.lexeme_to_gold
+            .iter()
+            .map(|gold| gold.last().map(|j| tags[*j]))
+            .collect()
+    }
+
+    pub fn score(&self) -> TokenizationScore {
+        TokenizationScore {
+            lexemes: self.lexeme_to_gold.len(),
+            gold: self.gold_to_lexeme.len(),
+            matched: (0..self.lexeme_to_gold.len())
+                .filter(|i| self.one_to_one(*i).is_some())
+                .count(),
+        }
+    }
+}
+
+// How well lexemes match gold tokens. A lexeme counts as correct if it aligns one-to-one,
+// so values that differ from the gold form (like "not" for "n't") still count
+// Scores of several sentences can be added up
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokenizationScore {
+    pub lexemes: usize,
+    pub gold: usize,
+    pub matched: usize,
+}
+
+impl TokenizationScore {
+    pub fn add(&mut self, other: TokenizationScore) {
+        self.lexemes += other.lexemes;
+        self.gold += other.gold;
+        self.matched += other.matched;
+    }
+
+    pub fn precision(&self) -> f64 {
+        self.matched as f64 / self.lexemes.max(1) as f64
+    }
+
+    pub fn recall(&self) -> f64 {
+        self.matched as f64 / self.gold.max(1) as f64
+    }
+
+    pub fn f1(&self) -> f64 {
+        let (precision, recall) = (self.precision(), self.recall());
+        if precision + recall == 0.0 {
+            0.0
+        } else {
+            2.0 * precision * recall / (precision + recall)
+        }
+    }
+}
+
+// Union-find over lexemes and gold tokens
+struct Groups {
+    parent: Vec<usize>,
+}
+
+impl Groups {
+    fn new(len: usize) -> Groups {
+        Groups {
+            parent: (0..len).collect(),
+        }
+    }
+
+    fn find(&mut self, mut x: usize) -> usize {
+        while self.parent[x] != x {
+            self.parent[x] = self.parent[self.parent[x]];
+            x = self.parent[x];
+        }
+        x
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parent[a.max(b)] = a.min(b);
+        }
+    }
+}
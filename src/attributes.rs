@@ -0,0 +1,492 @@
+use super::lexemizer::Case;
+
+// Lexical attributes of a vocab entry
+// These only depend on the string, so they are computed once per entry
+#[derive(Debug, Clone, std::cmp::Eq, std::cmp::PartialEq)]
+pub struct Attributes {
+    pub shape: Vec<u8>, // "xxxx", "dd.d", "xx'x"
+    pub is_alpha: bool,
+    pub is_digit: bool,
+    pub is_punct: bool,
+    pub like_num: bool,
+    pub like_url: bool,
+    pub is_stop: bool,
+    pub cluster: u64, // brown cluster, 0 if the entry is unclustered
+}
+
+impl Attributes {
+    pub fn of(value: &[u8]) -> Attributes {
+        Attributes {
+            shape: shape(value),
+            is_alpha: !value.is_empty() && value.iter().all(u8::is_ascii_alphabetic),
+            is_digit: !value.is_empty() && value.iter().all(u8::is_ascii_digit),
+            is_punct: !value.is_empty() && value.iter().all(u8::is_ascii_punctuation),
+            like_num: like_num(value),
+            like_url: like_url(value),
+            is_stop: STOP_WORDS.contains(&value),
+            cluster: 0,
+        }
+    }
+
+    // The boolean attributes as bits, in declaration order from is_alpha (1) to is_stop (32)
+    pub fn flags(&self) -> u8 {
+        [
+            self.is_alpha,
+            self.is_digit,
+            self.is_punct,
+            self.like_num,
+            self.like_url,
+            self.is_stop,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |flags, (i, set)| flags | (*set as u8) << i)
+    }
+
+    // Vocab entries are lowercase, so the shape needs the case of the token to be exact
+    // Mixed case can't be recovered, and is approximated by title case
+    pub fn cased_shape(&self, case: Case) -> Vec<u8> {
+        cased_shape(self.shape.clone(), case)
+    }
+}
+
+// A shape with case applied, see Attributes::cased_shape
+pub fn cased_shape(mut shape: Vec<u8>, case: Case) -> Vec<u8> {
+    match case {
+        Case::Upper => shape.make_ascii_uppercase(),
+        Case::Title | Case::Mixed => {
+            if let Some(first) = shape.iter_mut().find(|c| **c == b'x') {
+                *first = b'X';
+            }
+        }
+        Case::Lower | Case::Uncased => (),
+    }
+    shape
+}
+
+// Letters become x (or X), digits become d, everything else is kept
+// Runs of more than 4 of the same character are cut to 4
+pub fn shape(value: &[u8]) -> Vec<u8> {
+    let mut shape = Vec::new();
+    let mut run = 0;
+    for c in value {
+        let s = match c {
+            b'a'..=b'z' => b'x',
+            b'A'..=b'Z' => b'X',
+            b'0'..=b'9' => b'd',
+            _ => *c,
+        };
+        if shape.last() == Some(&s) {
+            run += 1;
+        } else {
+            run = 1;
+        }
+        if run <= 4 {
+            shape.push(s);
+        }
+    }
+    shape
+}
+
+// 10, 1,000, -3.5, 1/2, ten
+pub fn like_num(value: &[u8]) -> bool {
+    let value = value
+        .strip_prefix(b"-")
+        .or_else(|| value.strip_prefix(b"+"))
+        .or_else(|| value.strip_prefix(b"~"))
+        .unwrap_or(value);
+    let digits: Vec<u8> = value
+        .iter()
+        .copied()
+        .filter(|c| *c != b',' && *c != b'.')
+        .collect();
+    if !digits.is_empty() && digits.iter().all(u8::is_ascii_digit) {
+        return true;
+    }
+    if let Some(slash) = value.iter().position(|c| *c == b'/') {
+        let (numerator, denominator) = (&value[..slash], &value[slash + 1..]);
+        if !numerator.is_empty()
+            && !denominator.is_empty()
+            && numerator.iter().chain(denominator).all(u8::is_ascii_digit)
+        {
+            return true;
+        }
+    }
+    NUMBER_WORDS.contains(&value)
+}
+
+// http://x.com, www.x.com, x.org/y
+pub fn like_url(value: &[u8]) -> bool {
+    if value.starts_with(b"http://")
+        || value.starts_with(b"https://")
+        || value.starts_with(b"ftp://")
+        || value.starts_with(b"www.")
+    {
+        return value.len() > 8;
+    }
+    if value.contains(&b'@') {
+        return false;
+    }
+    let host = value.split(|c| *c == b'/').next().unwrap_or(value);
+    match host.iter().rposition(|c| *c == b'.') {
+        Some(dot) if dot > 0 => URL_SUFFIXES.contains(&&host[dot + 1..]),
+        _ => false,
+    }
+}
+
+const URL_SUFFIXES: &[&[u8]] = &[
+    b"com", b"org", b"net", b"edu", b"gov", b"mil", b"int", b"io", b"co", b"uk", b"us", b"ca",
+    b"de", b"fr", b"jp", b"au", b"ru", b"ch", b"it", b"nl", b"se", b"no", b"es", b"info", b"biz",
+    b"dev", b"app", b"ai", b"me", b"tv",
+];
+
+const NUMBER_WORDS: &[&[u8]] = &[
+    b"zero",
+    b"one",
+    b"two",
+    b"three",
+    b"four",
+    b"five",
+    b"six",
+    b"seven",
+    b"eight",
+    b"nine",
+    b"ten",
+    b"eleven",
+    b"twelve",
+    b"thirteen",
+    b"fourteen",
+    b"fifteen",
+    b"sixteen",
+    b"seventeen",
+    b"eighteen",
+    b"nineteen",
+    b"twenty",
+    b"thirty",
+    b"forty",
+    b"fifty",
+    b"sixty",
+    b"seventy",
+    b"eighty",
+    b"ninety",
+    b"hundred",
+    b"thousand",
+    b"million",
+    b"billion",
+    b"trillion",
+    b"quadrillion",
+];
+
+// Adapted from Spacy
+const STOP_WORDS: &[&[u8]] = &[
+    b"a",
+    b"about",
+    b"above",
+    b"across",
+    b"after",
+    b"afterwards",
+    b"again",
+    b"against",
+    b"all",
+    b"almost",
+    b"alone",
+    b"along",
+    b"already",
+    b"also",
+    b"although",
+    b"always",
+    b"am",
+    b"among",
+    b"amongst",
+    b"an",
+    b"and",
+    b"another",
+    b"any",
+    b"anyhow",
+    b"anyone",
+    b"anything",
+    b"anyway",
+    b"anywhere",
+    b"are",
+    b"around",
+    b"as",
+    b"at",
+    b"back",
+    b"be",
+    b"became",
+    b"because",
+    b"become",
+    b"becomes",
+    b"becoming",
+    b"been",
+    b"before",
+    b"beforehand",
+    b"behind",
+    b"being",
+    b"below",
+    b"beside",
+    b"besides",
+    b"between",
+    b"beyond",
+    b"both",
+    b"bottom",
+    b"but",
+    b"by",
+    b"ca",
+    b"call",
+    b"can",
+    b"cannot",
+    b"could",
+    b"did",
+    b"do",
+    b"does",
+    b"doing",
+    b"done",
+    b"down",
+    b"due",
+    b"during",
+    b"each",
+    b"eight",
+    b"either",
+    b"eleven",
+    b"else",
+    b"elsewhere",
+    b"empty",
+    b"enough",
+    b"even",
+    b"ever",
+    b"every",
+    b"everyone",
+    b"everything",
+    b"everywhere",
+    b"except",
+    b"few",
+    b"fifteen",
+    b"fifty",
+    b"first",
+    b"five",
+    b"for",
+    b"former",
+    b"formerly",
+    b"forty",
+    b"four",
+    b"from",
+    b"front",
+    b"full",
+    b"further",
+    b"get",
+    b"give",
+    b"go",
+    b"had",
+    b"has",
+    b"have",
+    b"he",
+    b"hence",
+    b"her",
+    b"here",
+    b"hereafter",
+    b"hereby",
+    b"herein",
+    b"hereupon",
+    b"hers",
+    b"herself",
+    b"him",
+    b"himself",
+    b"his",
+    b"how",
+    b"however",
+    b"hundred",
+    b"i",
+    b"if",
+    b"in",
+    b"indeed",
+    b"into",
+    b"is",
+    b"it",
+    b"its",
+    b"itself",
+    b"just",
+    b"keep",
+    b"last",
+    b"latter",
+    b"latterly",
+    b"least",
+    b"less",
+    b"made",
+    b"make",
+    b"many",
+    b"may",
+    b"me",
+    b"meanwhile",
+    b"might",
+    b"mine",
+    b"more",
+    b"moreover",
+    b"most",
+    b"mostly",
+    b"move",
+    b"much",
+    b"must",
+    b"my",
+    b"myself",
+    b"name",
+    b"namely",
+    b"neither",
+    b"never",
+    b"nevertheless",
+    b"next",
+    b"nine",
+    b"no",
+    b"nobody",
+    b"none",
+    b"noone",
+    b"nor",
+    b"not",
+    b"nothing",
+    b"now",
+    b"nowhere",
+    b"of",
+    b"off",
+    b"often",
+    b"on",
+    b"once",
+    b"one",
+    b"only",
+    b"onto",
+    b"or",
+    b"other",
+    b"others",
+    b"otherwise",
+    b"our",
+    b"ours",
+    b"ourselves",
+    b"out",
+    b"over",
+    b"own",
+    b"part",
+    b"per",
+    b"perhaps",
+    b"please",
+    b"put",
+    b"quite",
+    b"rather",
+    b"re",
+    b"really",
+    b"regarding",
+    b"same",
+    b"say",
+    b"see",
+    b"seem",
+    b"seemed",
+    b"seeming",
+    b"seems",
+    b"serious",
+    b"several",
+    b"she",
+    b"should",
+    b"show",
+    b"side",
+    b"since",
+    b"six",
+    b"sixty",
+    b"so",
+    b"some",
+    b"somehow",
+    b"someone",
+    b"something",
+    b"sometime",
+    b"sometimes",
+    b"somewhere",
+    b"still",
+    b"such",
+    b"take",
+    b"ten",
+    b"than",
+    b"that",
+    b"the",
+    b"their",
+    b"them",
+    b"themselves",
+    b"then",
+    b"thence",
+    b"there",
+    b"thereafter",
+    b"thereby",
+    b"therefore",
+    b"therein",
+    b"thereupon",
+    b"these",
+    b"they",
+    b"third",
+    b"this",
+    b"those",
+    b"though",
+    b"three",
+    b"through",
+    b"throughout",
+    b"thru",
+    b"thus",
+    b"to",
+    b"together",
+    b"too",
+    b"top",
+    b"toward",
+    b"towards",
+    b"twelve",
+    b"twenty",
+    b"two",
+    b"under",
+    b"unless",
+    b"until",
+    b"up",
+    b"upon",
+    b"us",
+    b"used",
+    b"using",
+    b"various",
+    b"very",
+    b"via",
+    b"was",
+    b"we",
+    b"well",
+    b"were",
+    b"what",
+    b"whatever",
+    b"when",
+    b"whence",
+    b"whenever",
+    b"where",
+    b"whereafter",
+    b"whereas",
+    b"whereby",
+    b"wherein",
+    b"whereupon",
+    b"wherever",
+    b"whether",
+    b"which",
+    b"while",
+    b"whither",
+    b"who",
+    b"whoever",
+    b"whole",
+    b"whom",
+    b"whose",
+    b"why",
+    b"will",
+    b"with",
+    b"within",
+    b"without",
+    b"would",
+    b"yet",
+    b"you",
+    b"your",
+    b"yours",
+    b"yourself",
+    b"yourselves",
+    b"'s",
+    b"'ll",
+    b"'d",
+    b"'re",
+    b"'ve",
+    b"n't",
+];
@@ -0,0 +1,359 @@
+use super::lexemizer::Lexeme;
+use super::lexemizer::RuleSet;
+use super::vocab::Vocab;
+use super::vocab::VocabIndex;
+use std::collections::HashMap;
+
+// Brown clustering, using the windowed algorithm from Liang's thesis:
+// https://cs.stanford.edu/~pliang/papers/meng-thesis.pdf
+// Words are added most frequent first. Whenever there are more than window clusters,
+// the pair whose merge loses the least mutual information between adjacent clusters is merged.
+// Once every word is in, the remaining clusters are merged down to one, giving a binary tree.
+//
+// The path from the root of the tree to a word is its cluster, stored in the vocab as a u64:
+// a leading 1 followed by the path bits (0 = left, 1 = right). So "0110" is 0b10110.
+// Paths longer than 63 bits are truncated.
+
+// Cluster the lexemes of a corpus, and write the paths into the vocab
+// Words seen fewer than min_count times are left unclustered
+// Returns the number of words clustered
+pub fn cluster_corpus(
+    ruleset: &RuleSet,
+    vocab: &mut Vocab,
+    documents: impl IntoIterator<Item = String>,
+    window: usize,
+    min_count: u64,
+) -> usize {
+    let documents: Vec<Vec<Lexeme<VocabIndex>>> = documents
+        .into_iter()
+        .map(|document| vocab.intern_lexemes(ruleset.lexemize(document)))
+        .collect();
+    cluster(vocab, &documents, window, min_count)
+}
+
+// Cluster already interned documents, and write the paths into the vocab
+// Bigrams are not counted across documents
+pub fn cluster(
+    vocab: &mut Vocab,
+    documents: &[Vec<Lexeme<VocabIndex>>],
+    window: usize,
+    min_count: u64,
+) -> usize {
+    let window = window.max(1);
+    let mut counts: HashMap<VocabIndex, u64> = HashMap::new();
+    let mut bigrams: HashMap<(VocabIndex, VocabIndex), u64> = HashMap::new();
+    for document in documents {
+        for lexeme in document {
+            *counts.entry(lexeme.value).or_default() += 1;
+        }
+        for pair in document.windows(2) {
+            *bigrams.entry((pair[0].value, pair[1].value)).or_default() += 1;
+        }
+    }
+
+    let mut words: Vec<VocabIndex> = counts
+        .iter()
+        .filter(|(index, count)| **count >= min_count && **index != VocabIndex::OOV)
+        .map(|(index, _)| *index)
+        .collect();
+    words.sort_by_key(|index| (std::cmp::Reverse(counts[index]), *index));
+    if words.is_empty() {
+        return 0;
+    }
+    let word_ids: HashMap<VocabIndex, usize> = words
+        .iter()
+        .enumerate()
+        .map(|(i, word)| (*word, i))
+        .collect();
+
+    // adjacency between clustered words, and how often each word is on either side of a bigram
+    let mut right = vec![Vec::new(); words.len()];
+    let mut left = vec![Vec::new(); words.len()];
+    let mut left_totals = vec![0u64; words.len()];
+    let mut right_totals = vec![0u64; words.len()];
+    let mut total = 0;
+    for ((a, b), count) in bigrams.iter() {
+        total += count;
+        if let Some(a) = word_ids.get(a) {
+            left_totals[*a] += count;
+        }
+        if let Some(b) = word_ids.get(b) {
+            right_totals[*b] += count;
+        }
+        if let (Some(a), Some(b)) = (word_ids.get(a), word_ids.get(b)) {
+            right[*a].push((*b, *count));
+            left[*b].push((*a, *count));
+        }
+    }
+
+    let mut state = State::new(window + 1, total.max(1) as f64);
+    for word in 0..words.len() {
+        state.add(
+            word,
+            left_totals[word],
+            right_totals[word],
+            &right[word],
+            &left[word],
+        );
+        if state.active.len() > window {
+            state.merge_best();
+        }
+    }
+    while state.active.len() > 1 {
+        state.merge_best();
+    }
+
+    // walk the tree, assigning paths to the leaves
+    let root = state.node[state.active[0]];
+    let mut stack = vec![(root, 1u64, 0)];
+    while let Some((node, path, depth)) = stack.pop() {
+        match state.tree[node] {
+            Node::Leaf(word) => {
+                vocab.set_cluster(words[word], path);
+            }
+            Node::Merge(a, b) => {
+                if depth < 63 {
+                    stack.push((a, path << 1, depth + 1));
+                    stack.push((b, path << 1 | 1, depth + 1));
+                } else {
+                    stack.push((a, path, depth));
+                    stack.push((b, path, depth));
+                }
+            }
+        }
+    }
+    words.len()
+}
+
+// The first bits of a cluster path, e.g. for 4, 6 and 10 bit prefix features
+// Paths shorter than bits are returned whole
+pub fn cluster_prefix(cluster: u64, bits: u32) -> u64 {
+    if cluster == 0 {
+        return 0;
+    }
+    let length = 63 - cluster.leading_zeros();
+    if length <= bits {
+        cluster
+    } else {
+        cluster >> (length - bits)
+    }
+}
+
+// The path as a string of 0s and 1s
+pub fn cluster_path(cluster: u64) -> String {
+    if cluster == 0 {
+        return String::new();
+    }
+    let length = 63 - cluster.leading_zeros();
+    (0..length)
+        .rev()
+        .map(|bit| if cluster >> bit & 1 == 1 { '1' } else { '0' })
+        .collect()
+}
+
+enum Node {
+    Leaf(usize),
+    Merge(usize, usize),
+}
+
+// Clusters live in a fixed number of slots. Merging frees a slot for the next word
+struct State {
+    total: f64,
+    // bigram counts between slots
+    counts: Vec<Vec<f64>>,
+    // how often each slot is on the left/right of any bigram
+    left: Vec<f64>,
+    right: Vec<f64>,
+    // loss of mutual information from merging two slots, for i < j
+    loss: Vec<Vec<f64>>,
+    active: Vec<usize>,
+    free: Vec<usize>,
+    // the tree node, and the words, in each slot
+    node: Vec<usize>,
+    members: Vec<Vec<usize>>,
+    slot_of: HashMap<usize, usize>,
+    tree: Vec<Node>,
+}
+
+impl State {
+    fn new(slots: usize, total: f64) -> State {
+        State {
+            total,
+            counts: vec![vec![0.0; slots]; slots],
+            left: vec![0.0; slots],
+            right: vec![0.0; slots],
+            loss: vec![vec![0.0; slots]; slots],
+            active: Vec::new(),
+            free: (0..slots).rev().collect(),
+            node: vec![0; slots],
+            members: vec![Vec::new(); slots],
+            slot_of: HashMap::new(),
+            tree: Vec::new(),
+        }
+    }
+
+    // Contribution of a cell to the mutual information
+    fn q(&self, count: f64, left: f64, right: f64) -> f64 {
+        if count <= 0.0 {
+            return 0.0;
+        }
+        let p = count / self.total;
+        p * (p * self.total * self.total / (left * right)).ln()
+    }
+
+    fn q_slots(&self, a: usize, b: usize) -> f64 {
+        self.q(self.counts[a][b], self.left[a], self.right[b])
+    }
+
+    // Contribution of the cells between the merge of i and j, and k (both directions)
+    fn q_merged(&self, i: usize, j: usize, k: usize) -> f64 {
+        let (left, right) = (self.left[i] + self.left[j], self.right[i] + self.right[j]);
+        self.q(self.counts[i][k] + self.counts[j][k], left, self.right[k])
+            + self.q(self.counts[k][i] + self.counts[k][j], self.left[k], right)
+    }
+
+    // Contribution of the cells between slot a, and k (both directions)
+    fn q_both(&self, a: usize, k: usize) -> f64 {
+        self.q_slots(a, k) + self.q_slots(k, a)
+    }
+
+    // Every cell involving a, counting the diagonal once
+    fn q_row(&self, a: usize) -> f64 {
+        self.active.iter().map(|k| self.q_both(a, *k)).sum::<f64>() - self.q_slots(a, a)
+    }
+
+    fn full_loss(&self, i: usize, j: usize, rows: &HashMap<usize, f64>) -> f64 {
+        let before = rows[&i] + rows[&j] - self.q_slots(i, j) - self.q_slots(j, i);
+        let mut after = 0.0;
+        for k in self.active.iter().copied() {
+            if k != i && k != j {
+                after += self.q_merged(i, j, k);
+            }
+        }
+        let inner = self.counts[i][i] + self.counts[i][j] + self.counts[j][i] + self.counts[j][j];
+        after += self.q(
+            inner,
+            self.left[i] + self.left[j],
+            self.right[i] + self.right[j],
+        );
+        before - after
+    }
+
+    fn set_loss(&mut self, i: usize, j: usize, loss: f64) {
+        self.loss[i.min(j)][i.max(j)] = loss;
+    }
+
+    fn get_loss(&self, i: usize, j: usize) -> f64 {
+        self.loss[i.min(j)][i.max(j)]
+    }
+
+    fn pairs(&self) -> Vec<(usize, usize)> {
+        let mut pairs = Vec::new();
+        for (x, i) in self.active.iter().enumerate() {
+            for j in self.active[x + 1..].iter() {
+                pairs.push((*i, *j));
+            }
+        }
+        pairs
+    }
+
+    fn add(
+        &mut self,
+        word: usize,
+        left: u64,
+        right: u64,
+        right_neighbours: &[(usize, u64)],
+        left_neighbours: &[(usize, u64)],
+    ) {
+        let s = self.free.pop().unwrap();
+        for k in 0..self.counts.len() {
+            self.counts[s][k] = 0.0;
+            self.counts[k][s] = 0.0;
+        }
+        for (other, count) in right_neighbours {
+            if *other == word {
+                self.counts[s][s] += *count as f64;
+            } else if let Some(k) = self.slot_of.get(other) {
+                self.counts[s][*k] += *count as f64;
+            }
+        }
+        for (other, count) in left_neighbours {
+            if let Some(k) = self.slot_of.get(other).filter(|_| *other != word) {
+                self.counts[*k][s] += *count as f64;
+            }
+        }
+        self.left[s] = left as f64;
+        self.right[s] = right as f64;
+        self.node[s] = self.tree.len();
+        self.tree.push(Node::Leaf(word));
+        self.members[s] = vec![word];
+        self.slot_of.insert(word, s);
+
+        // existing pairs gain the cells between them and the new slot
+        for (i, j) in self.pairs() {
+            let delta = self.q_both(i, s) + self.q_both(j, s) - self.q_merged(i, j, s);
+            self.set_loss(i, j, self.get_loss(i, j) + delta);
+        }
+        self.active.push(s);
+        let rows: HashMap<usize, f64> = self.active.iter().map(|k| (*k, self.q_row(*k))).collect();
+        for k in self.active.clone() {
+            if k != s {
+                let loss = self.full_loss(k, s, &rows);
+                self.set_loss(k, s, loss);
+            }
+        }
+    }
+
+    fn merge_best(&mut self) {
+        let (a, b) = self
+            .pairs()
+            .into_iter()
+            .min_by(|x, y| self.get_loss(x.0, x.1).total_cmp(&self.get_loss(y.0, y.1)))
+            .unwrap();
+        let others: Vec<(usize, usize)> = self
+            .pairs()
+            .into_iter()
+            .filter(|(i, j)| *i != a && *i != b && *j != a && *j != b)
+            .collect();
+
+        // remove what the cells of a and b contributed to every other pair's loss
+        for (i, j) in others.iter().copied() {
+            let old = self.q_both(i, a) + self.q_both(i, b) + self.q_both(j, a) + self.q_both(j, b)
+                - self.q_merged(i, j, a)
+                - self.q_merged(i, j, b);
+            self.set_loss(i, j, self.get_loss(i, j) - old);
+        }
+
+        // merge b into a
+        for k in 0..self.counts.len() {
+            self.counts[a][k] += self.counts[b][k];
+        }
+        for k in 0..self.counts.len() {
+            self.counts[k][a] += self.counts[k][b];
+        }
+        self.left[a] += self.left[b];
+        self.right[a] += self.right[b];
+        self.active.retain(|k| *k != b);
+        self.free.push(b);
+        self.tree.push(Node::Merge(self.node[a], self.node[b]));
+        self.node[a] = self.tree.len() - 1;
+        let moved = std::mem::take(&mut self.members[b]);
+        for word in moved.iter() {
+            self.slot_of.insert(*word, a);
+        }
+        self.members[a].extend(moved);
+
+        // and add back what the merged cluster contributes
+        for (i, j) in others.iter().copied() {
+            let new = self.q_both(i, a) + self.q_both(j, a) - self.q_merged(i, j, a);
+            self.set_loss(i, j, self.get_loss(i, j) + new);
+        }
+        let rows: HashMap<usize, f64> = self.active.iter().map(|k| (*k, self.q_row(*k))).collect();
+        for k in self.active.clone() {
+            if k != a {
+                let loss = self.full_loss(k, a, &rows);
+                self.set_loss(k, a, loss);
+            }
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use super::lexemizer::Lexeme;
+use super::tokenizer::TagSet;
+use super::tokenizer::Tagger;
+
+// How well the tagger's scores (see ScoredTag) match its accuracy on held out data
+// Tags are put in bins by score: a well calibrated tagger is right about 90% of the time
+// on tags it scores around 0.9
+#[derive(Debug, Clone)]
+pub struct Calibration {
+    pub bins: Vec<Bin>, // bin i holds scores from i / bins to (i + 1) / bins
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bin {
+    pub tags: usize,
+    pub correct: usize,
+    pub score: f64, // the sum of the scores
+}
+
+impl Bin {
+    pub fn accuracy(&self) -> f64 {
+        self.correct as f64 / self.tags.max(1) as f64
+    }
+
+    pub fn mean_score(&self) -> f64 {
+        self.score / self.tags.max(1) as f64
+    }
+}
+
+impl Calibration {
+    pub fn new(bins: usize) -> Calibration {
+        Calibration {
+            bins: vec![Bin::default(); bins.max(1)],
+        }
+    }
+
+    // Tag held out sentences, and compare each tag's score with the gold tag
+    pub fn measure<T: TagSet>(
+        tagger: &Tagger<T>,
+        sentences: &[Vec<(Lexeme, T)>],
+        bins: usize,
+    ) -> Calibration {
+        let mut calibration = Calibration::new(bins);
+        for sentence in sentences {
+            let lexemes: Vec<Lexeme> = sentence.iter().map(|(lexeme, _)| lexeme.clone()).collect();
+            for (tag, (_, gold)) in tagger.tag_scored(&lexemes, 0).iter().zip(sentence) {
+                calibration.add(tag.score, tag.part_of_speech == *gold);
+            }
+        }
+        calibration
+    }
+
+    pub fn add(&mut self, score: f32, correct: bool) {
+        let bins = self.bins.len();
+        let bin = &mut self.bins[((score.clamp(0.0, 1.0) * bins as f32) as usize).min(bins - 1)];
+        bin.tags += 1;
+        bin.correct += correct as usize;
+        bin.score += score as f64;
+    }
+
+    pub fn tags(&self) -> usize {
+        self.bins.iter().map(|bin| bin.tags).sum()
+    }
+
+    pub fn accuracy(&self) -> f64 {
+        let correct: usize = self.bins.iter().map(|bin| bin.correct).sum();
+        correct as f64 / self.tags().max(1) as f64
+    }
+
+    pub fn mean_score(&self) -> f64 {
+        let score: f64 = self.bins.iter().map(|bin| bin.score).sum();
+        score / self.tags().max(1) as f64
+    }
+
+    // Expected calibration error: the gap between score and accuracy in each bin,
+    // weighted by the share of tags in the bin. 0 is perfectly calibrated
+    pub fn expected_error(&self) -> f64 {
+        let tags = self.tags().max(1) as f64;
+        self.bins
+            .iter()
+            .map(|bin| (bin.mean_score() - bin.accuracy()).abs() * bin.tags as f64 / tags)
+            .sum()
+    }
+}
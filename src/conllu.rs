@@ -0,0 +1,150 @@
+use super::invalid_data;
+use super::lexemizer::normalize;
+use super::lexemizer::Case;
+use super::lexemizer::Lexeme;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::path::Path;
+
+// Reader for CoNLL-U treebanks:
+// https://universaldependencies.org/format.html
+// Only the columns the tagger needs are kept. Empty nodes (ids like 8.1) are skipped
+
+// A syntactic word. Words of a multiword token (ids like 1-2) share the token's span
+#[derive(Debug, Clone)]
+pub struct Word {
+    pub form: String,
+    pub lemma: String,
+    pub upos: String,
+    pub xpos: String, // "_" when the treebank has no language specific tags
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Sentence {
+    pub text: String, // the token forms, joined by spaces except where SpaceAfter=No
+    pub words: Vec<Word>,
+}
+
+impl Sentence {
+    // One lexeme per word, normalized the same way the lexemizer does
+    pub fn lexemes(&self) -> Vec<Lexeme> {
+        self.words
+            .iter()
+            .map(|word| Lexeme {
+                value: normalize(&word.form),
+                start: word.start,
+                end: word.end,
+                case: Case::of(&word.form),
+            })
+            .collect()
+    }
+}
+
+pub struct Reader<R> {
+    lines: io::Lines<R>,
+    line: usize,
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(reader: R) -> Reader<R> {
+        Reader {
+            lines: reader.lines(),
+            line: 0,
+        }
+    }
+
+    fn read_sentence(&mut self) -> io::Result<Option<Sentence>> {
+        let mut sentence = Sentence {
+            text: String::new(),
+            words: Vec::new(),
+        };
+        // the last word of the multiword token being read, and its span
+        let mut multiword: Option<(usize, usize, usize)> = None;
+        let mut started = false;
+        for line in self.lines.by_ref() {
+            let line = line?;
+            self.line += 1;
+            if line.trim().is_empty() {
+                if started {
+                    break;
+                }
+                continue;
+            }
+            started = true;
+            if line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 10 {
+                return Err(invalid_data(&format!(
+                    "line {}: expected 10 columns",
+                    self.line
+                )));
+            }
+            let (id, form, misc) = (fields[0], fields[1], fields[9]);
+            if id.contains('.') {
+                continue;
+            }
+            let space_after = !misc.split('|').any(|field| field == "SpaceAfter=No");
+            if let Some((first, last)) = id.split_once('-') {
+                let last = first
+                    .parse::<usize>()
+                    .and(last.parse::<usize>())
+                    .map_err(|_| {
+                        invalid_data(&format!("line {}: bad multiword token id", self.line))
+                    })?;
+                let (start, end) = push_token(&mut sentence.text, form, space_after);
+                multiword = Some((last, start, end));
+                continue;
+            }
+            let id: usize = id
+                .parse()
+                .map_err(|_| invalid_data(&format!("line {}: bad word id", self.line)))?;
+            let (start, end) = match multiword {
+                Some((last, start, end)) if id <= last => (start, end),
+                _ => push_token(&mut sentence.text, form, space_after),
+            };
+            sentence.words.push(Word {
+                form: form.to_string(),
+                lemma: fields[2].to_string(),
+                upos: fields[3].to_string(),
+                xpos: fields[4].to_string(),
+                start,
+                end,
+            });
+        }
+        if !started {
+            return Ok(None);
+        }
+        let trimmed = sentence.text.trim_end().len();
+        sentence.text.truncate(trimmed);
+        Ok(Some(sentence))
+    }
+}
+
+impl<R: BufRead> Iterator for Reader<R> {
+    type Item = io::Result<Sentence>;
+
+    fn next(&mut self) -> Option<io::Result<Sentence>> {
+        self.read_sentence().transpose()
+    }
+}
+
+pub fn read_file(path: impl AsRef<Path>) -> io::Result<Vec<Sentence>> {
+    Reader::new(BufReader::new(File::open(path)?)).collect()
+}
+
+// Appends a token to the text, and returns its span
+fn push_token(text: &mut String, form: &str, space_after: bool) -> (usize, usize) {
+    let start = text.len();
+    text.push_str(form);
+    let end = text.len();
+    if space_after {
+        text.push(' ');
+    }
+    (start, end)
+}
@@ -0,0 +1,245 @@
+use super::tokenizer::TagSet;
+
+// How the tagger searches for the tags of a sentence
+// Greedy takes the best tag of each word in turn, so an early mistake is seen by every later
+// tag feature. Beam keeps the best width partial taggings instead. Viterbi finds the best
+// tagging exactly, and only works when no template looks further back than the previous tag
+#[derive(Debug, Clone, Copy, std::cmp::Eq, std::cmp::PartialEq)]
+pub enum Decoder {
+    Greedy,
+    Beam(usize), // width, at least 1
+    Viterbi,
+}
+
+// Which mistake a beam trained tagger learns from, as described here:
+// https://aclanthology.org/N12-1015.pdf
+#[derive(Debug, Clone, Copy, std::cmp::Eq, std::cmp::PartialEq)]
+pub enum Update {
+    Early,        // the first word at which the gold tags fall out of the beam (Collins and Roark)
+    MaxViolation, // the word at which the best tagging beats the gold tags by the most (Huang et al.)
+}
+
+// Scores of the tags of one sentence
+pub(crate) trait Scorer<T: TagSet> {
+    fn len(&self) -> usize;
+
+    // The tags word i can have, in TagSet::ALL order. Never empty
+    // A word with one candidate is never scored, and adds nothing to the score of a tagging
+    fn candidates(&self, i: usize) -> &[T];
+
+    // The score of each tag of word i (by TagSet::index), given the tags of the two words
+    // before it. They replace what was in scores
+    fn scores(
+        &mut self,
+        i: usize,
+        previous: Option<T>,
+        previous2: Option<T>,
+        scores: &mut Vec<f32>,
+    );
+}
+
+// The best tag of each word in turn
+pub(crate) fn greedy<T: TagSet>(scorer: &mut impl Scorer<T>) -> Vec<T> {
+    let mut tags = Vec::with_capacity(scorer.len());
+    let (mut previous, mut previous2) = (None, None);
+    let mut scores = Vec::new();
+    for i in 0..scorer.len() {
+        let tag = match scorer.candidates(i) {
+            [tag] => *tag,
+            _ => {
+                scorer.scores(i, previous, previous2, &mut scores);
+                best(&scores, scorer.candidates(i))
+            }
+        };
+        tags.push(tag);
+        previous2 = previous;
+        previous = Some(tag);
+    }
+    tags
+}
+
+// The best scoring of the candidates, or of every tag if there are none
+// Ties go to the tag that comes first in TagSet::ALL, so decoding is deterministic
+pub(crate) fn best<T: TagSet>(scores: &[f32], candidates: &[T]) -> T {
+    let candidates = match candidates {
+        [] => T::ALL,
+        candidates => candidates,
+    };
+    let mut best = candidates[0];
+    for tag in candidates {
+        if scores[tag.index()] > scores[best.index()] {
+            best = *tag;
+        }
+    }
+    best
+}
+
+// A partial tagging: its last tag, and the item it extends in the step before
+#[derive(Clone, Copy)]
+struct Item<T> {
+    tag: T,
+    parent: usize,
+    score: f32,
+    gold: bool, // whether every tag so far is gold
+}
+
+// Beam search. Taggings that end in the same two tags can't be told apart by later features,
+// so only the best of them is kept
+// With gold tags, stops at the point given by update, and returns the best tagging up to there
+// (the gold tags up to there are the ones to learn from). Otherwise returns the best tagging
+pub(crate) fn beam<T: TagSet>(
+    scorer: &mut impl Scorer<T>,
+    width: usize,
+    gold: Option<(&[T], Update)>,
+) -> Vec<T> {
+    let width = width.max(1);
+    let mut steps: Vec<Vec<Item<T>>> = Vec::with_capacity(scorer.len());
+    let mut gold_score = 0.0;
+    // the step where the best tagging is furthest ahead of the gold tags, and by how much
+    let mut most_violated: Option<(usize, f32)> = None;
+    let (mut expanded, mut scores) = (Vec::new(), Vec::new());
+    for i in 0..scorer.len() {
+        let candidates = scorer.candidates(i);
+        let fixed = candidates.len() == 1;
+        let candidates = candidates.to_vec();
+        let parents = match steps.last() {
+            Some(step) => step.clone(),
+            None => vec![Item {
+                tag: candidates[0], // never read
+                parent: 0,
+                score: 0.0,
+                gold: true,
+            }],
+        };
+        expanded.clear();
+        for (p, parent) in parents.iter().enumerate() {
+            let (previous, previous2) = history(&steps, i, p);
+            if !fixed {
+                scorer.scores(i, previous, previous2, &mut scores);
+            }
+            for tag in candidates.iter() {
+                expanded.push(Item {
+                    tag: *tag,
+                    parent: p,
+                    score: parent.score + if fixed { 0.0 } else { scores[tag.index()] },
+                    gold: parent.gold && gold.is_some_and(|(gold, _)| gold[i] == *tag),
+                });
+            }
+        }
+        // stable, so ties go to the earlier parent, then the earlier tag
+        expanded.sort_by(|a, b| b.score.total_cmp(&a.score));
+        let mut step: Vec<Item<T>> = Vec::with_capacity(width);
+        for item in expanded.iter() {
+            if step.len() == width {
+                break;
+            }
+            let key =
+                |item: &Item<T>| (item.tag, i.checked_sub(1).map(|_| parents[item.parent].tag));
+            if !step.iter().any(|kept| key(kept) == key(item)) {
+                step.push(*item);
+            }
+        }
+
+        if let Some((gold, update)) = gold {
+            if !fixed {
+                let previous = i.checked_sub(1).map(|j| gold[j]);
+                let previous2 = i.checked_sub(2).map(|j| gold[j]);
+                scorer.scores(i, previous, previous2, &mut scores);
+                gold_score += scores[gold[i].index()];
+            }
+            let best = step[0];
+            if !best.gold && most_violated.is_none_or(|(_, most)| best.score - gold_score > most) {
+                most_violated = Some((i, best.score - gold_score));
+            }
+            let fallen_out = !step.iter().any(|item| item.gold);
+            steps.push(step);
+            if fallen_out && update == Update::Early {
+                break;
+            }
+            continue;
+        }
+        steps.push(step);
+    }
+
+    let last = steps.len().saturating_sub(1);
+    let last = match gold {
+        // the gold tags won
+        Some((gold, _))
+            if steps.len() == scorer.len() && steps[last].first().is_none_or(|best| best.gold) =>
+        {
+            return gold.to_vec()
+        }
+        Some((_, Update::MaxViolation)) => most_violated.map_or(last, |(i, _)| i),
+        Some((_, Update::Early)) | None => last,
+    };
+    steps.truncate(last + 1);
+    let mut tags = Vec::with_capacity(steps.len());
+    let mut at = 0;
+    for step in steps.iter().rev() {
+        tags.push(step[at].tag);
+        at = step[at].parent;
+    }
+    tags.reverse();
+    tags
+}
+
+// The tags before item p of the step before word i
+fn history<T: TagSet>(steps: &[Vec<Item<T>>], i: usize, p: usize) -> (Option<T>, Option<T>) {
+    if i == 0 {
+        return (None, None);
+    }
+    let item = steps[i - 1][p];
+    let previous2 = i.checked_sub(2).map(|j| steps[j][item.parent].tag);
+    (Some(item.tag), previous2)
+}
+
+// Exact search, for scores that only depend on the previous tag (previous2 is always None)
+pub(crate) fn viterbi<T: TagSet>(scorer: &mut impl Scorer<T>) -> Vec<T> {
+    // per word, the best score of a tagging ending in each candidate, and the candidate before it
+    let mut steps: Vec<Vec<(f32, usize)>> = Vec::with_capacity(scorer.len());
+    let mut scores = Vec::new();
+    for i in 0..scorer.len() {
+        let fixed = scorer.candidates(i).len() == 1;
+        let previous: Vec<Option<T>> = match i {
+            0 => vec![None],
+            _ => scorer
+                .candidates(i - 1)
+                .iter()
+                .map(|tag| Some(*tag))
+                .collect(),
+        };
+        let mut step = vec![(f32::NEG_INFINITY, 0); scorer.candidates(i).len()];
+        for (p, tag) in previous.into_iter().enumerate() {
+            let score = steps.last().map_or(0.0, |step| step[p].0);
+            if !fixed {
+                scorer.scores(i, tag, None, &mut scores);
+            }
+            for (t, candidate) in scorer.candidates(i).iter().enumerate() {
+                let score = score
+                    + if fixed {
+                        0.0
+                    } else {
+                        scores[candidate.index()]
+                    };
+                if score > step[t].0 {
+                    step[t] = (score, p);
+                }
+            }
+        }
+        steps.push(step);
+    }
+
+    let mut tags = Vec::with_capacity(steps.len());
+    let mut at = match steps.last() {
+        Some(step) => {
+            (0..step.len()).fold(0, |best, t| if step[t].0 > step[best].0 { t } else { best })
+        }
+        None => return tags,
+    };
+    for (i, step) in steps.iter().enumerate().rev() {
+        tags.push(scorer.candidates(i)[at]);
+        at = step[at].1;
+    }
+    tags.reverse();
+    tags
+}
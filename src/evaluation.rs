@@ -0,0 +1,330 @@
+use super::alignment::Alignment;
+use super::alignment::TokenizationScore;
+use super::conllu;
+use super::lexemizer::normalize;
+use super::lexemizer::RuleSet;
+use super::tokenizer::gold_tags;
+use super::tokenizer::PartOfSpeech;
+use super::tokenizer::TagColumn;
+use super::tokenizer::TagSet;
+use super::tokenizer::Tagger;
+use std::collections::HashSet;
+use std::io;
+use std::marker::PhantomData;
+
+// Tagger accuracy on a gold treebank, tagging what the lexemizer makes of each sentence's text
+// Lexemes are aligned with gold words (see Alignment). A gold word is tagged right if it aligns
+// one-to-one with a lexeme of the same tag, so tokenization mistakes count against accuracy.
+// Scores of several treebanks can be added up
+#[derive(Debug, Clone)]
+pub struct Evaluation<T: TagSet = PartOfSpeech> {
+    pub tokenization: TokenizationScore,
+    pub words: Accuracy,   // every gold word
+    pub known: Accuracy,   // gold words in the known words (see known_words)
+    pub unknown: Accuracy, // the rest
+    // gold tag by predicted tag, for gold words aligned one-to-one, indexed by TagSet::index
+    pub confusion: Vec<Vec<usize>>,
+    pub gold: Vec<usize>,      // gold words with each tag
+    pub predicted: Vec<usize>, // lexemes tagged with each tag
+    tags: PhantomData<T>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Accuracy {
+    pub words: usize,
+    pub correct: usize,
+}
+
+impl Accuracy {
+    pub fn accuracy(&self) -> f64 {
+        self.correct as f64 / self.words.max(1) as f64
+    }
+
+    fn add(&mut self, correct: bool) {
+        self.words += 1;
+        self.correct += correct as usize;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TagScore {
+    pub gold: usize,
+    pub predicted: usize,
+    pub correct: usize,
+}
+
+impl TagScore {
+    pub fn precision(&self) -> f64 {
+        self.correct as f64 / self.predicted.max(1) as f64
+    }
+
+    pub fn recall(&self) -> f64 {
+        self.correct as f64 / self.gold.max(1) as f64
+    }
+
+    pub fn f1(&self) -> f64 {
+        let (precision, recall) = (self.precision(), self.recall());
+        if precision + recall == 0.0 {
+            0.0
+        } else {
+            2.0 * precision * recall / (precision + recall)
+        }
+    }
+}
+
+// The normalized form of every word of some treebanks, usually the training data
+pub fn known_words(sentences: &[conllu::Sentence]) -> HashSet<Vec<u8>> {
+    sentences
+        .iter()
+        .flat_map(|sentence| sentence.words.iter())
+        .map(|word| normalize(&word.form))
+        .collect()
+}
+
+impl<T: TagSet> Default for Evaluation<T> {
+    fn default() -> Evaluation<T> {
+        let tags = T::ALL.len();
+        Evaluation {
+            tokenization: TokenizationScore::default(),
+            words: Accuracy::default(),
+            known: Accuracy::default(),
+            unknown: Accuracy::default(),
+            confusion: vec![vec![0; tags]; tags],
+            gold: vec![0; tags],
+            predicted: vec![0; tags],
+            tags: PhantomData,
+        }
+    }
+}
+
+impl<T: TagSet> Evaluation<T> {
+    pub fn evaluate(
+        tagger: &Tagger<T>,
+        ruleset: &RuleSet,
+        sentences: &[conllu::Sentence],
+        column: TagColumn,
+        known: &HashSet<Vec<u8>>,
+    ) -> io::Result<Evaluation<T>> {
+        let mut evaluation = Evaluation::default();
+        for sentence in sentences {
+            evaluation.add_sentence(tagger, ruleset, sentence, column, known)?;
+        }
+        Ok(evaluation)
+    }
+
+    pub fn add_sentence(
+        &mut self,
+        tagger: &Tagger<T>,
+        ruleset: &RuleSet,
+        sentence: &conllu::Sentence,
+        column: TagColumn,
+        known: &HashSet<Vec<u8>>,
+    ) -> io::Result<()> {
+        let gold = gold_tags::<T>(sentence, column)?;
+        let lexemes = ruleset.lexemize(sentence.text.clone());
+        let predicted = tagger.tag(&lexemes);
+        let alignment = Alignment::conllu(&lexemes, sentence);
+        self.tokenization.add(alignment.score());
+        for tag in predicted.iter() {
+            self.predicted[tag.index()] += 1;
+        }
+        for (j, (word, gold)) in sentence.words.iter().zip(gold).enumerate() {
+            self.gold[gold.index()] += 1;
+            let guess = match alignment.gold_to_lexeme[j][..] {
+                [i] if alignment.one_to_one(i) == Some(j) => Some(predicted[i]),
+                _ => None,
+            };
+            if let Some(guess) = guess {
+                self.confusion[gold.index()][guess.index()] += 1;
+            }
+            let correct = guess == Some(gold);
+            self.words.add(correct);
+            if known.contains(&normalize(&word.form)) {
+                self.known.add(correct);
+            } else {
+                self.unknown.add(correct);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn add(&mut self, other: &Evaluation<T>) {
+        self.tokenization.add(other.tokenization);
+        for (accuracy, other) in [
+            (&mut self.words, other.words),
+            (&mut self.known, other.known),
+            (&mut self.unknown, other.unknown),
+        ] {
+            accuracy.words += other.words;
+            accuracy.correct += other.correct;
+        }
+        for (row, other) in self.confusion.iter_mut().zip(other.confusion.iter()) {
+            for (count, other) in row.iter_mut().zip(other) {
+                *count += other;
+            }
+        }
+        for (count, other) in self.gold.iter_mut().zip(other.gold.iter()) {
+            *count += other;
+        }
+        for (count, other) in self.predicted.iter_mut().zip(other.predicted.iter()) {
+            *count += other;
+        }
+    }
+
+    pub fn tag_score(&self, tag: T) -> TagScore {
+        let tag = tag.index();
+        TagScore {
+            gold: self.gold[tag],
+            predicted: self.predicted[tag],
+            correct: self.confusion[tag][tag],
+        }
+    }
+
+    // Tags that are in the gold data or were predicted, in TagSet::ALL order
+    pub fn tags(&self) -> Vec<T> {
+        T::ALL
+            .iter()
+            .filter(|tag| self.gold[tag.index()] + self.predicted[tag.index()] > 0)
+            .copied()
+            .collect()
+    }
+
+    // A report for people: accuracy, a table of per tag scores, and the confusion matrix
+    // (rows are gold tags, columns predicted tags, and only the tags of tags())
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for (name, accuracy) in [
+            ("accuracy", &self.words),
+            ("known words", &self.known),
+            ("unknown words", &self.unknown),
+        ] {
+            text += &format!(
+                "{}: {:.2}% of {} words\n",
+                name,
+                accuracy.accuracy() * 100.0,
+                accuracy.words
+            );
+        }
+        text += &format!(
+            "tokenization: precision {:.2}%, recall {:.2}%, f1 {:.2}%\n",
+            self.tokenization.precision() * 100.0,
+            self.tokenization.recall() * 100.0,
+            self.tokenization.f1() * 100.0
+        );
+
+        let tags = self.tags();
+        // wide enough for every tag name
+        let width = tags.iter().map(|tag| tag.name().len()).fold(6, usize::max);
+        text += &format!(
+            "\n{:<width$} {:>9} {:>9} {:>9} {:>7} {:>9}\n",
+            "tag", "precision", "recall", "f1", "gold", "predicted"
+        );
+        for tag in tags.iter() {
+            let score = self.tag_score(*tag);
+            text += &format!(
+                "{:<width$} {:>8.2}% {:>8.2}% {:>8.2}% {:>7} {:>9}\n",
+                tag.name(),
+                score.precision() * 100.0,
+                score.recall() * 100.0,
+                score.f1() * 100.0,
+                score.gold,
+                score.predicted
+            );
+        }
+
+        text += &format!("\n{:<width$}", "");
+        for tag in tags.iter() {
+            text += &format!(" {:>width$}", tag.name());
+        }
+        text.push('\n');
+        for gold in tags.iter() {
+            text += &format!("{:<width$}", gold.name());
+            for predicted in tags.iter() {
+                let count = self.confusion[gold.index()][predicted.index()];
+                text += &format!(" {:>width$}", count);
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    // The same report as a JSON object, with tags by name:
+    // {"accuracy": {"words", "correct", "accuracy"}, "known": ..., "unknown": ...,
+    //  "tokenization": {"lexemes", "gold", "matched", "precision", "recall", "f1"},
+    //  "tags": {tag: {"gold", "predicted", "correct", "precision", "recall", "f1"}},
+    //  "confusion": {gold tag: {predicted tag: count}}}
+    // Only nonzero counts are in the confusion matrix
+    pub fn json(&self) -> String {
+        let accuracy = |accuracy: &Accuracy| {
+            format!(
+                "{{\"words\": {}, \"correct\": {}, \"accuracy\": {}}}",
+                accuracy.words,
+                accuracy.correct,
+                accuracy.accuracy()
+            )
+        };
+        let tokenization = &self.tokenization;
+        let mut json = format!(
+            "{{\"accuracy\": {}, \"known\": {}, \"unknown\": {}, \"tokenization\": {{\"lexemes\": {}, \"gold\": {}, \"matched\": {}, \"precision\": {}, \"recall\": {}, \"f1\": {}}}, \"tags\": {{",
+            accuracy(&self.words),
+            accuracy(&self.known),
+            accuracy(&self.unknown),
+            tokenization.lexemes,
+            tokenization.gold,
+            tokenization.matched,
+            tokenization.precision(),
+            tokenization.recall(),
+            tokenization.f1()
+        );
+        let tags = self.tags();
+        let scores: Vec<String> = tags
+            .iter()
+            .map(|tag| {
+                let score = self.tag_score(*tag);
+                format!(
+                    "{}: {{\"gold\": {}, \"predicted\": {}, \"correct\": {}, \"precision\": {}, \"recall\": {}, \"f1\": {}}}",
+                    json_string(tag.name()),
+                    score.gold,
+                    score.predicted,
+                    score.correct,
+                    score.precision(),
+                    score.recall(),
+                    score.f1()
+                )
+            })
+            .collect();
+        json += &scores.join(", ");
+        json += "}, \"confusion\": {";
+        let rows: Vec<String> = tags
+            .iter()
+            .map(|gold| {
+                let counts: Vec<String> = tags
+                    .iter()
+                    .map(|predicted| (predicted, self.confusion[gold.index()][predicted.index()]))
+                    .filter(|(_, count)| *count > 0)
+                    .map(|(predicted, count)| {
+                        format!("{}: {}", json_string(predicted.name()), count)
+                    })
+                    .collect();
+                format!("{}: {{{}}}", json_string(gold.name()), counts.join(", "))
+            })
+            .collect();
+        json += &rows.join(", ");
+        json += "}}";
+        json
+    }
+}
+
+fn json_string(string: &str) -> String {
+    let mut json = String::from("\"");
+    for c in string.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if (c as u32) < 0x20 => json += &format!("\\u{:04x}", c as u32),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
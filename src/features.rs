@@ -0,0 +1,416 @@
+use super::attributes::Attributes;
+use super::brown::cluster_prefix;
+use super::lexemizer::Case;
+use super::lexemizer::Lexeme;
+use super::tokenizer::TagSet;
+use super::vocab::Vocab;
+use super::Fnv;
+use std::collections::HashMap;
+
+// Feature templates for the tagger, after the Honnibal and Collins taggers:
+// https://explosion.ai/blog/part-of-speech-pos-tagger-in-python
+// https://www.cs.columbia.edu/~mcollins/papers/tagperc.pdf
+// Word offsets are relative to the word being tagged: 0 is the word, -1 the one before it.
+// Words past either end of the sentence have no features
+#[derive(Debug, std::hash::Hash, Clone, Copy, std::cmp::Eq, std::cmp::PartialEq)]
+pub enum Template {
+    Bias,
+    Word(i8),
+    Suffix(i8, u8), // the last n bytes, 1 to 7
+    Prefix(i8, u8), // the first n bytes, 1 to 7
+    Shape(i8),      // "Xxxx", "dd.d" (see attributes::shape)
+    Case(i8),
+    HasHyphen(i8),
+    HasDigit(i8),
+    Lexical(i8),     // like_num, is_stop and the other flags, see Attributes::flags
+    Tag(i8),         // the tag chosen for an earlier word, -1 or -2
+    TagPair,         // the tags of the two words before
+    TagWord(i8),     // the tag of the word before, with the word at an offset
+    Cluster(i8, u8), // the first n bits of the brown cluster, see brown::cluster_prefix
+}
+
+impl Template {
+    pub const WORDS: &'static [Template] = &[
+        Template::Word(0),
+        Template::Word(-1),
+        Template::Word(1),
+        Template::Word(-2),
+        Template::Word(2),
+    ];
+    pub const AFFIXES: &'static [Template] = &[
+        Template::Suffix(0, 3),
+        Template::Prefix(0, 1),
+        Template::Suffix(-1, 3),
+        Template::Suffix(1, 3),
+    ];
+    pub const ORTHOGRAPHY: &'static [Template] = &[
+        Template::Shape(0),
+        Template::Case(0),
+        Template::HasHyphen(0),
+        Template::HasDigit(0),
+        Template::Lexical(0),
+    ];
+    pub const TAGS: &'static [Template] =
+        &[Template::Tag(-1), Template::Tag(-2), Template::TagPair];
+    pub const CONJUNCTIONS: &'static [Template] = &[Template::TagWord(0)];
+    // needs clusters (see TrainOptions::clusters)
+    pub const CLUSTERS: &'static [Template] = &[
+        Template::Cluster(0, 4),
+        Template::Cluster(0, 6),
+        Template::Cluster(0, 10),
+        Template::Cluster(-1, 4),
+        Template::Cluster(1, 4),
+    ];
+
+    // Every group but clusters
+    pub fn standard() -> Vec<Template> {
+        Template::groups(&[
+            Template::WORDS,
+            Template::AFFIXES,
+            Template::ORTHOGRAPHY,
+            Template::TAGS,
+            Template::CONJUNCTIONS,
+        ])
+    }
+
+    // How many of the tags before the word the template looks at
+    pub fn history(&self) -> usize {
+        match self {
+            Template::Tag(-1) | Template::TagWord(_) => 1,
+            Template::Tag(_) | Template::TagPair => 2,
+            _ => 0,
+        }
+    }
+
+    // "words", "affixes", "orthography", "tags", "conjunctions" or "clusters"
+    pub fn group(name: &str) -> Option<&'static [Template]> {
+        match name {
+            "words" => Some(Template::WORDS),
+            "affixes" => Some(Template::AFFIXES),
+            "orthography" => Some(Template::ORTHOGRAPHY),
+            "tags" => Some(Template::TAGS),
+            "conjunctions" => Some(Template::CONJUNCTIONS),
+            "clusters" => Some(Template::CLUSTERS),
+            _ => None,
+        }
+    }
+
+    // Bias, and the templates of each group
+    pub fn groups(groups: &[&[Template]]) -> Vec<Template> {
+        let mut templates = vec![Template::Bias];
+        for template in groups.iter().flat_map(|group| group.iter()) {
+            if !templates.contains(template) {
+                templates.push(*template);
+            }
+        }
+        templates
+    }
+
+    // As stored in model files, e.g. "suffix3(-1)" or "tag(-1)+word(0)"
+    pub fn name(&self) -> String {
+        match self {
+            Template::Bias => "bias".to_string(),
+            Template::Word(i) => format!("word({})", i),
+            Template::Suffix(i, n) => format!("suffix{}({})", n, i),
+            Template::Prefix(i, n) => format!("prefix{}({})", n, i),
+            Template::Shape(i) => format!("shape({})", i),
+            Template::Case(i) => format!("case({})", i),
+            Template::HasHyphen(i) => format!("hyphen({})", i),
+            Template::HasDigit(i) => format!("digit({})", i),
+            Template::Lexical(i) => format!("lexical({})", i),
+            Template::Tag(i) => format!("tag({})", i),
+            Template::TagPair => "tag(-1,-2)".to_string(),
+            Template::TagWord(i) => format!("tag(-1)+word({})", i),
+            Template::Cluster(i, n) => format!("cluster{}({})", n, i),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Template> {
+        match name {
+            "bias" => return Some(Template::Bias),
+            "tag(-1,-2)" => return Some(Template::TagPair),
+            _ => (),
+        }
+        let (head, offset) = name.strip_suffix(')')?.rsplit_once('(')?;
+        let offset: i8 = offset.parse().ok()?;
+        let length = |prefix: &str| -> Option<u8> {
+            let n: u8 = head.strip_prefix(prefix)?.parse().ok()?;
+            Some(n).filter(|n| (1..=7).contains(n))
+        };
+        Some(match head {
+            "word" => Template::Word(offset),
+            "shape" => Template::Shape(offset),
+            "case" => Template::Case(offset),
+            "hyphen" => Template::HasHyphen(offset),
+            "digit" => Template::HasDigit(offset),
+            "lexical" => Template::Lexical(offset),
+            "tag" if offset == -1 || offset == -2 => Template::Tag(offset),
+            "tag(-1)+word" => Template::TagWord(offset),
+            _ if head.starts_with("suffix") => Template::Suffix(offset, length("suffix")?),
+            _ if head.starts_with("prefix") => Template::Prefix(offset, length("prefix")?),
+            _ if head.starts_with("cluster") => {
+                let bits: u8 = head.strip_prefix("cluster")?.parse().ok()?;
+                Template::Cluster(offset, bits.min(63))
+            }
+            _ => return None,
+        })
+    }
+}
+
+// Brown clusters of words, copied out of a vocab so a model doesn't need the vocab to tag
+#[derive(Debug, Clone, Default)]
+pub struct Clusters {
+    pub(crate) words: HashMap<u64, u64>, // word hash to cluster
+    pub(crate) vocab_hash: u64,
+}
+
+impl Clusters {
+    pub fn from_vocab(vocab: &Vocab) -> Clusters {
+        let words = vocab
+            .iter()
+            .filter_map(|(index, value)| match vocab.attributes(index)?.cluster {
+                0 => None,
+                cluster => Some((word_hash(value), cluster)),
+            })
+            .collect();
+        Clusters {
+            words,
+            vocab_hash: vocab.hash(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+}
+
+// Up to 7 bytes from the start or end of a lexeme, kept inline so features don't borrow or allocate
+#[derive(Debug, std::hash::Hash, Clone, Copy, std::cmp::Eq, std::cmp::PartialEq)]
+pub(crate) struct Affix {
+    pub(crate) len: u8,
+    pub(crate) bytes: [u8; 7],
+}
+
+impl Affix {
+    pub(crate) fn new(value: &[u8]) -> Affix {
+        let mut bytes = [0; 7];
+        bytes[..value.len()].copy_from_slice(value);
+        Affix {
+            len: value.len() as u8,
+            bytes,
+        }
+    }
+
+    fn prefix(value: &[u8], length: usize) -> Affix {
+        Affix::new(&value[..length.min(value.len()).min(7)])
+    }
+
+    fn suffix(value: &[u8], length: usize) -> Affix {
+        let length = length.min(value.len()).min(7);
+        Affix::new(&value[value.len() - length..])
+    }
+}
+
+// Words and shapes are kept as 64 bit hashes (see word_hash)
+#[derive(Debug, std::hash::Hash, Clone, Copy, std::cmp::Eq, std::cmp::PartialEq)]
+pub(crate) enum Feature {
+    Bias,
+    Suffix {
+        word_index: i8, // refers to the index of the word. Current word is 0. Next word is 1, Previous word is -1
+        value: Affix,   // the chars making up the last part
+    },
+    Prefix {
+        word_index: i8,
+        value: Affix, // the chars making up the first part
+    },
+    Tag {
+        word_index: i8,
+        value: Option<u8>, // the tag's TagSet::index, None before the start of the sentence
+    },
+    DoubleTag {
+        word_indexes: (i8, i8),
+        values: (Option<u8>, Option<u8>),
+    },
+    Cluster {
+        word_index: i8,
+        value: u64, // the first 4, 6 or 10 bits of the brown cluster (see brown::cluster_prefix)
+    },
+    Word {
+        word_index: i8,
+        value: u64,
+    },
+    Shape {
+        word_index: i8,
+        value: u64,
+    },
+    Case {
+        word_index: i8,
+        value: Case,
+    },
+    HasHyphen {
+        word_index: i8,
+        value: bool,
+    },
+    HasDigit {
+        word_index: i8,
+        value: bool,
+    },
+    Lexical {
+        word_index: i8,
+        value: u8, // Attributes::flags
+    },
+    TagWord {
+        word_index: i8, // of the word. The tag is always the previous word's
+        tag: Option<u8>,
+        value: u64,
+    },
+}
+
+// What the templates need to know about each word of a sentence, worked out once per sentence
+pub(crate) struct Word<'a> {
+    value: &'a [u8],
+    case: Case,
+    pub(crate) hash: u64, // see word_hash
+    shape: u64,
+    lexical: u8,
+    cluster: u64,
+}
+
+pub(crate) fn words<'a>(
+    lexemes: impl Iterator<Item = &'a Lexeme>,
+    templates: &[Template],
+    clusters: &Clusters,
+    vocab: Option<&Vocab>,
+) -> Vec<Word<'a>> {
+    let needed = templates
+        .iter()
+        .any(|t| matches!(t, Template::Shape(_) | Template::Lexical(_)));
+    lexemes
+        .map(|lexeme| {
+            let hash = word_hash(&lexeme.value);
+            // attributes only depend on the string, so words in the vocab use its entry's
+            let (shape, lexical) = if needed {
+                let stored =
+                    vocab.and_then(|vocab| vocab.attributes(vocab.get_id_by_str(&lexeme.value)?));
+                let computed;
+                let attributes = match stored {
+                    Some(attributes) => attributes,
+                    None => {
+                        computed = Attributes::of(&lexeme.value);
+                        &computed
+                    }
+                };
+                (
+                    word_hash(&attributes.cased_shape(lexeme.case)),
+                    attributes.flags(),
+                )
+            } else {
+                (0, 0)
+            };
+            Word {
+                value: &lexeme.value,
+                case: lexeme.case,
+                hash,
+                shape,
+                lexical,
+                cluster: clusters.words.get(&hash).copied().unwrap_or(0),
+            }
+        })
+        .collect()
+}
+
+// The features of words[i], given the tags already chosen for the two words before it
+// They replace what was in features, so one buffer can be reused for every word
+pub(crate) fn features<T: TagSet>(
+    features: &mut Vec<Feature>,
+    templates: &[Template],
+    words: &[Word],
+    i: usize,
+    previous: Option<T>,
+    previous2: Option<T>,
+) {
+    features.clear();
+    let previous = previous.map(|tag| tag.index() as u8);
+    let previous2 = previous2.map(|tag| tag.index() as u8);
+    for template in templates {
+        let word_index = match template {
+            Template::Bias | Template::Tag(_) | Template::TagPair => 0,
+            Template::Word(i)
+            | Template::Suffix(i, _)
+            | Template::Prefix(i, _)
+            | Template::Shape(i)
+            | Template::Case(i)
+            | Template::HasHyphen(i)
+            | Template::HasDigit(i)
+            | Template::Lexical(i)
+            | Template::TagWord(i)
+            | Template::Cluster(i, _) => *i,
+        };
+        let at = i as isize + word_index as isize;
+        let word = match words.get(at as usize) {
+            Some(word) if at >= 0 => word,
+            _ => continue,
+        };
+        features.push(match *template {
+            Template::Bias => Feature::Bias,
+            Template::Word(_) => Feature::Word {
+                word_index,
+                value: word.hash,
+            },
+            Template::Suffix(_, n) => Feature::Suffix {
+                word_index,
+                value: Affix::suffix(word.value, n as usize),
+            },
+            Template::Prefix(_, n) => Feature::Prefix {
+                word_index,
+                value: Affix::prefix(word.value, n as usize),
+            },
+            Template::Shape(_) => Feature::Shape {
+                word_index,
+                value: word.shape,
+            },
+            Template::Case(_) => Feature::Case {
+                word_index,
+                value: word.case,
+            },
+            Template::HasHyphen(_) => Feature::HasHyphen {
+                word_index,
+                value: word.value.contains(&b'-'),
+            },
+            Template::HasDigit(_) => Feature::HasDigit {
+                word_index,
+                value: word.value.iter().any(u8::is_ascii_digit),
+            },
+            Template::Lexical(_) => Feature::Lexical {
+                word_index,
+                value: word.lexical,
+            },
+            Template::Tag(tag_index) => Feature::Tag {
+                word_index: tag_index,
+                value: if tag_index == -1 { previous } else { previous2 },
+            },
+            Template::TagPair => Feature::DoubleTag {
+                word_indexes: (-1, -2),
+                values: (previous, previous2),
+            },
+            Template::TagWord(_) => Feature::TagWord {
+                word_index,
+                tag: previous,
+                value: word.hash,
+            },
+            Template::Cluster(_, bits) => Feature::Cluster {
+                word_index,
+                value: cluster_prefix(word.cluster, bits as u32),
+            },
+        });
+    }
+}
+
+// Stored in model files, so it has to be stable
+pub(crate) fn word_hash(value: &[u8]) -> u64 {
+    Fnv::hash(value)
+}
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::iter::FromIterator;
+use unicode_general_category::{get_general_category, GeneralCategory};
 use unidecode::unidecode;
 
 // Convert tiny string into smolstr
@@ -34,16 +35,121 @@ macro_rules! cat {
     };
 }
 
-#[derive(Debug)]
-pub struct Lexeme {
-    pub value: Vec<u8>,
+// The value is either the lexeme's own bytes, or a VocabIndex once interned
+#[derive(Debug, Clone)]
+pub struct Lexeme<V = Vec<u8>> {
+    pub value: V,
+    // byte offsets of the text this lexeme came from
+    // lexemes produced by a special expand all share the span of the original word
+    pub start: usize,
+    pub end: usize,
+    // the value is lowercase, so remember the case of the original text
+    pub case: Case,
+}
+
+impl<V> Lexeme<V> {
+    // Move the span of this lexeme forward by offset bytes
+    pub fn shift(self, offset: usize) -> Lexeme<V> {
+        Lexeme {
+            start: self.start + offset,
+            end: self.end + offset,
+            ..self
+        }
+    }
+
+    // Replace the value, keeping span and case
+    pub fn map<W>(self, f: impl FnOnce(V) -> W) -> Lexeme<W> {
+        Lexeme {
+            value: f(self.value),
+            start: self.start,
+            end: self.end,
+            case: self.case,
+        }
+    }
+}
+
+#[derive(Debug, std::hash::Hash, Clone, Copy, std::cmp::Eq, std::cmp::PartialEq)]
+pub enum Case {
+    Lower,   // word
+    Title,   // Word
+    Upper,   // WORD, I
+    Mixed,   // wOrD, McDonald
+    Uncased, // 123, ...
+}
+
+impl Case {
+    pub fn of(string: &str) -> Case {
+        let mut cased = string
+            .chars()
+            .filter(|c| c.is_uppercase() || c.is_lowercase());
+        let first = match cased.next() {
+            Some(first) => first,
+            None => return Case::Uncased,
+        };
+        let (mut upper, mut lower) = (0, 0);
+        for c in cased {
+            if c.is_uppercase() {
+                upper += 1;
+            } else {
+                lower += 1;
+            }
+        }
+        match (first.is_uppercase(), upper, lower) {
+            (false, 0, _) => Case::Lower,
+            (true, _, 0) => Case::Upper,
+            (true, 0, _) => Case::Title,
+            _ => Case::Mixed,
+        }
+    }
+}
+
+// Classes of single characters that may be split off the start or end of a word
+// Membership is decided on the raw character (before transliteration),
+// using its unicode general category
+#[derive(Debug, std::hash::Hash, Clone, Copy, std::cmp::Eq, std::cmp::PartialEq)]
+pub enum CharClass {
+    OpenBracket,  // Ps: ( [ { « etc
+    CloseBracket, // Pe: ) ] } » etc
+    Quote,        // Pi, Pf and the ascii quotes: ' " ` ‘ ’ “ ”
+    Currency,     // Sc: $ € £ ¥ etc
+    Dash,         // Pd: - – — etc
+    Punct,        // Po: . , ! ? ; : % & @ # … etc
+}
+
+impl CharClass {
+    pub fn contains(&self, c: char) -> bool {
+        match get_general_category(c) {
+            GeneralCategory::OpenPunctuation => *self == CharClass::OpenBracket,
+            GeneralCategory::ClosePunctuation => *self == CharClass::CloseBracket,
+            GeneralCategory::InitialPunctuation | GeneralCategory::FinalPunctuation => {
+                *self == CharClass::Quote
+            }
+            GeneralCategory::CurrencySymbol => *self == CharClass::Currency,
+            GeneralCategory::DashPunctuation => *self == CharClass::Dash,
+            // ascii quotes are categorized as Po, but behave like Pi/Pf
+            GeneralCategory::OtherPunctuation => match c {
+                '\'' | '"' => *self == CharClass::Quote,
+                _ => *self == CharClass::Punct,
+            },
+            // grave accent is Sk
+            GeneralCategory::ModifierSymbol if c == '`' => *self == CharClass::Quote,
+            _ => false,
+        }
+    }
+}
+
+// Transliterate to ascii and lowercase
+pub fn normalize(string: &str) -> Vec<u8> {
+    unidecode(string).to_ascii_lowercase().into_bytes()
 }
 
 pub struct RuleSet {
     // General Prefixes
-    general_prefix: HashSet<Vec<u8>>, // Prefixes
+    general_prefix: HashSet<Vec<u8>>, // Multi character prefixes (..., --, etc)
+    general_prefix_class: HashSet<CharClass>, // Single character prefixes
     // General Suffixes
-    general_suffix: HashSet<Vec<u8>>, // Suffixes (n't, 've, etc)
+    general_suffix: HashSet<Vec<u8>>, // Multi character suffixes (..., --, etc)
+    general_suffix_class: HashSet<CharClass>, // Single character suffixes
     special_expand: HashMap<Vec<u8>, Vec<Vec<u8>>>, // N.Y.. U.S., etc
 }
 
@@ -51,34 +157,28 @@ impl RuleSet {
     // Adapted from Spacy
     pub fn english() -> RuleSet {
         // Prefixes
-        let general_prefixes = HashSet::from_iter(vec![
-            sv!(b"("),
-            sv!(b")"),
-            sv!(b"$"),
-            sv!(b"#"),
-            sv!(b"."),
-            sv!(b"'"),
-            sv!(b"\""),
-            sv!(b".."),
-            sv!(b"..."),
-            sv!(b"&"),
-            sv!(b"@"),
-            sv!(b"?"),
+        let general_prefixes =
+            HashSet::from_iter(vec![sv!(b".."), sv!(b"..."), sv!(b"--"), sv!(b"---")]);
+
+        // No dashes, so that negative numbers stay whole
+        let general_prefix_classes = HashSet::from_iter(vec![
+            CharClass::OpenBracket,
+            CharClass::CloseBracket,
+            CharClass::Quote,
+            CharClass::Currency,
+            CharClass::Punct,
         ]);
 
-        let general_suffixes = HashSet::from_iter(vec![
-            sv!(b"("),
-            sv!(b")"),
-            sv!(b"$"),
-            sv!(b"#"),
-            sv!(b"."),
-            sv!(b"'"),
-            sv!(b"\""),
-            sv!(b".."),
-            sv!(b"..."),
-            sv!(b"&"),
-            sv!(b"@"),
-            sv!(b"?"),
+        let general_suffixes =
+            HashSet::from_iter(vec![sv!(b".."), sv!(b"..."), sv!(b"--"), sv!(b"---")]);
+
+        let general_suffix_classes = HashSet::from_iter(vec![
+            CharClass::OpenBracket,
+            CharClass::CloseBracket,
+            CharClass::Quote,
+            CharClass::Currency,
+            CharClass::Dash,
+            CharClass::Punct,
         ]);
 
         let mut special = HashMap::new();
@@ -2365,92 +2465,163 @@ impl RuleSet {
 
         RuleSet {
             general_prefix: general_prefixes,
+            general_prefix_class: general_prefix_classes,
             general_suffix: general_suffixes,
+            general_suffix_class: general_suffix_classes,
             special_expand: special,
         }
     }
 
     // If there is an exact match between this string and a special expand,
     // We create a set of lexemes with canonicals and the text
-    pub fn special_expand<'doc>(&self, string: &'doc [u8]) -> Option<Vec<Lexeme>> {
+    // Spans cover the whole of the given string
+    pub fn special_expand(&self, string: &[u8]) -> Option<Vec<Lexeme>> {
         let ret = self.special_expand.get(string);
-        if let Some(vs) = ret {
-            Some(
-                vs.iter()
-                    .map(|canonical| Lexeme {
-                        value: canonical.clone(),
-                    })
-                    .collect(),
-            )
-        } else {
-            None
-        }
+        ret.map(|vs| {
+            vs.iter()
+                .map(|canonical| Lexeme {
+                    value: canonical.clone(),
+                    start: 0,
+                    end: string.len(),
+                    case: Case::Lower,
+                })
+                .collect()
+        })
     }
 
     // Matches the longest prefix
-    // Returns A remainder, and a prefix lexeme
-    pub fn general_prefix_remainder<'doc>(
-        &self,
-        string: &'doc [u8],
-    ) -> Option<(Lexeme, &'doc [u8])> {
+    // Falls back to a single character from one of the prefix classes
+    // Returns A remainder, and a prefix lexeme (with span relative to string)
+    pub fn general_prefix_remainder<'doc>(&self, string: &'doc str) -> Option<(Lexeme, &'doc str)> {
         for i in (1..string.len()).rev() {
-            if let Some(prefix_canonical) = self.general_prefix.get(&string[..i]) {
+            if !string.is_char_boundary(i) {
+                continue;
+            }
+            if let Some(prefix_canonical) = self.general_prefix.get(&string.as_bytes()[..i]) {
                 return Some((
                     Lexeme {
                         value: prefix_canonical.clone(),
+                        start: 0,
+                        end: i,
+                        case: Case::Uncased,
                     },
                     &string[i..],
                 ));
             }
         }
+        let c = string.chars().next()?;
+        let i = c.len_utf8();
+        if i < string.len()
+            && self
+                .general_prefix_class
+                .iter()
+                .any(|class| class.contains(c))
+        {
+            return Some((
+                Lexeme {
+                    value: normalize(&string[..i]),
+                    start: 0,
+                    end: i,
+                    case: Case::of(&string[..i]),
+                },
+                &string[i..],
+            ));
+        }
         None
     }
 
     // Matches the longest suffix
-    pub fn general_suffix_remainder<'doc>(
-        &self,
-        string: &'doc [u8],
-    ) -> Option<(Lexeme, &'doc [u8])> {
+    // Falls back to a single character from one of the suffix classes
+    pub fn general_suffix_remainder<'doc>(&self, string: &'doc str) -> Option<(Lexeme, &'doc str)> {
         for i in 1..string.len() {
-            if let Some(suffix_canonical) = self.general_suffix.get(&string[i..]) {
+            if !string.is_char_boundary(i) {
+                continue;
+            }
+            if let Some(suffix_canonical) = self.general_suffix.get(&string.as_bytes()[i..]) {
                 return Some((
                     Lexeme {
                         value: suffix_canonical.clone(),
+                        start: i,
+                        end: string.len(),
+                        case: Case::Uncased,
                     },
                     &string[..i],
                 ));
             }
         }
+        let c = string.chars().next_back()?;
+        let i = string.len() - c.len_utf8();
+        if i > 0
+            && self
+                .general_suffix_class
+                .iter()
+                .any(|class| class.contains(c))
+        {
+            return Some((
+                Lexeme {
+                    value: normalize(&string[i..]),
+                    start: i,
+                    end: string.len(),
+                    case: Case::of(&string[i..]),
+                },
+                &string[..i],
+            ));
+        }
         None
     }
 
-    // Lexemize LOWERCASE string
+    // Lexemize string, transliterating to LOWERCASE ascii
     // Uses spacy algorithm
+    // Lexeme spans are byte offsets into string
     pub fn lexemize(&self, string: String) -> Vec<Lexeme> {
         let mut lexemes = Vec::new();
+        // every substr we look at is a slice of string
+        let offset = |substr: &str| substr.as_ptr() as usize - string.as_ptr() as usize;
 
-        for s in unidecode(&string).to_ascii_lowercase().split_whitespace() {
-            let mut substr = s.as_bytes();
+        for s in string.split_whitespace() {
+            let mut substr = s;
+            // suffixes are found back to front, so we hold them until the word is done
+            let mut suffixes = Vec::new();
             loop {
-                if let Some(mut tokvec) = self.special_expand(&substr) {
-                    lexemes.append(&mut tokvec);
+                let start = offset(substr);
+                if let Some(tokvec) = self.special_expand(&normalize(substr)) {
+                    // "Don't" is "Do not", "DON'T" is "DO NOT"
+                    let case = Case::of(substr);
+                    lexemes.extend(tokvec.into_iter().enumerate().map(|(i, lexeme)| Lexeme {
+                        start,
+                        end: start + substr.len(),
+                        case: match case {
+                            Case::Title | Case::Mixed if i > 0 => Case::Lower,
+                            _ => case,
+                        },
+                        ..lexeme
+                    }));
                     // this will cause us to start viewing the next substr
                     break;
-                } else if let Some((lexeme, remainder)) = self.general_prefix_remainder(&substr) {
-                    lexemes.push(lexeme);
+                } else if let Some((lexeme, remainder)) = self.general_prefix_remainder(substr) {
+                    lexemes.push(lexeme.shift(start));
                     substr = remainder;
                     continue;
-                } else if let Some((lexeme, remainder)) = self.general_suffix_remainder(&substr) {
-                    lexemes.push(lexeme);
+                } else if let Some((lexeme, remainder)) = self.general_suffix_remainder(substr) {
+                    suffixes.push(lexeme.shift(start));
                     substr = remainder;
                     continue;
                 }
                 // If we can't do anything with it, push lexeme
-                lexemes.push(Lexeme {
-                    value: Vec::from(substr),
-                });
+                // Transliteration may introduce spaces (e.g. for CJK), so split again
+                for value in normalize(substr).split(|b| b.is_ascii_whitespace()) {
+                    if !value.is_empty() {
+                        lexemes.push(Lexeme {
+                            value: Vec::from(value),
+                            start,
+                            end: start + substr.len(),
+                            case: Case::of(substr),
+                        });
+                    }
+                }
                 break;
             }
+            lexemes.extend(suffixes.into_iter().rev());
         }
         lexemes
     }
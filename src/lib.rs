@@ -1,4 +1,55 @@
-#![feature(hash_set_entry)]
+use std::hash::Hasher;
+use std::io;
+
+pub mod alignment;
+pub mod attributes;
+pub mod brown;
+pub mod calibration;
+pub mod conllu;
+pub mod decoder;
+pub mod evaluation;
+pub mod features;
+pub mod subword;
 pub mod tokenizer;
+pub mod universal;
 pub mod lexemizer;
+pub mod markup;
 pub mod parser;
+pub mod shared_vocab;
+pub mod vectors;
+pub mod vocab;
+
+pub(crate) fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// FNV-1a, which is stable across platforms and releases (unlike DefaultHasher), as model and
+// vocab files need. It is also faster than SipHash for the small keys the tagger looks up
+pub(crate) struct Fnv(u64);
+
+impl Fnv {
+    pub(crate) fn hash(bytes: &[u8]) -> u64 {
+        let mut hasher = Fnv::default();
+        hasher.write(bytes);
+        hasher.finish()
+    }
+}
+
+impl Default for Fnv {
+    fn default() -> Fnv {
+        Fnv(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
@@ -0,0 +1,539 @@
+use super::lexemizer::Lexeme;
+use super::lexemizer::RuleSet;
+
+// Strips HTML and Markdown down to plain text before lexemization,
+// remembering where every byte of the plain text came from in the raw document
+
+#[derive(Debug, Clone, Copy, std::cmp::Eq, std::cmp::PartialEq)]
+pub enum BlockKind {
+    Paragraph,
+    Heading(u8), // level 1 to 6
+    ListItem,
+    Quote,
+}
+
+// A block of plain text. Offsets are into Document::text
+#[derive(Debug, Clone, Copy)]
+pub struct Block {
+    pub kind: BlockKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug)]
+pub struct Document {
+    // plain text, with blocks separated by newlines
+    pub text: String,
+    pub blocks: Vec<Block>,
+    // raw span that produced each byte of text
+    spans: Vec<(usize, usize)>,
+    raw_len: usize,
+}
+
+impl Document {
+    pub fn from_html(raw: &str) -> Document {
+        let mut builder = Builder::new();
+        let mut i = 0;
+        while i < raw.len() {
+            let rest = &raw[i..];
+            if rest.starts_with("<!--") {
+                i += rest.find("-->").map(|end| end + 3).unwrap_or(rest.len());
+                continue;
+            }
+            if let Some(tag) = parse_tag(rest) {
+                i += tag.len;
+                match tag.name.as_str() {
+                    "script" | "style" if !tag.closing => i += raw_text_len(&raw[i..], &tag.name),
+                    "h1" | "h2" | "h3" | "h4" | "h5" | "h6" if !tag.closing => {
+                        builder.open(BlockKind::Heading(tag.name.as_bytes()[1] - b'0'))
+                    }
+                    "li" | "dt" | "dd" if !tag.closing => builder.open(BlockKind::ListItem),
+                    "blockquote" if !tag.closing => builder.open(BlockKind::Quote),
+                    "p" | "div" | "br" | "hr" | "tr" | "td" | "th" | "pre" | "section"
+                    | "article" | "header" | "footer" | "table" | "title"
+                        if !tag.closing =>
+                    {
+                        builder.open(BlockKind::Paragraph)
+                    }
+                    "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "li" | "dt" | "dd" | "blockquote"
+                    | "p" | "div" | "tr" | "td" | "th" | "pre" | "section" | "article"
+                    | "header" | "footer" | "table" | "title" | "ul" | "ol" | "dl" => {
+                        builder.close()
+                    }
+                    // inline tags vanish without a trace
+                    _ => (),
+                }
+                continue;
+            }
+            i += builder.push_char_or_entity(raw, i);
+        }
+        builder.close();
+        builder.finish(raw.len())
+    }
+
+    pub fn from_markdown(raw: &str) -> Document {
+        let mut builder = Builder::new();
+        // the fence that opened the code block we are inside of
+        let mut fence: Option<&str> = None;
+        let mut line_start = 0;
+        for line in raw.split_inclusive('\n') {
+            let content = line.trim_end_matches(&['\n', '\r'][..]);
+            let trimmed = content.trim_start();
+            let mut start = line_start + content.len() - trimmed.len();
+            let end = line_start + content.len();
+            line_start += line.len();
+
+            if let Some(f) = fence {
+                if trimmed.starts_with(f) {
+                    fence = None;
+                }
+                continue;
+            }
+            if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                builder.close();
+                fence = Some(&trimmed[..3]);
+                continue;
+            }
+            if trimmed.is_empty() {
+                builder.close();
+                continue;
+            }
+            if is_rule(trimmed) {
+                // setext heading underline
+                if builder.current == Some(BlockKind::Paragraph) {
+                    if trimmed.starts_with('=') {
+                        builder.current = Some(BlockKind::Heading(1));
+                    } else if trimmed.starts_with('-') {
+                        builder.current = Some(BlockKind::Heading(2));
+                    }
+                }
+                builder.close();
+                continue;
+            }
+            // link reference definitions and table delimiter rows carry no text
+            if (trimmed.starts_with('[') && trimmed.contains("]:"))
+                || (trimmed.starts_with('|') && trimmed.chars().all(|c| "|:- ".contains(c)))
+            {
+                continue;
+            }
+
+            let mut kind = None;
+            while raw[start..end].starts_with('>') {
+                start += 1;
+                start += raw[start..end].len() - raw[start..end].trim_start().len();
+                kind = Some(BlockKind::Quote);
+            }
+            if let Some((marker, level)) = heading_marker(&raw[start..end]) {
+                start += marker;
+                kind = Some(BlockKind::Heading(level));
+            } else if let Some(marker) = list_marker(&raw[start..end]) {
+                start += marker;
+                kind = Some(BlockKind::ListItem);
+            }
+
+            match kind {
+                Some(BlockKind::Heading(level)) => {
+                    builder.open(BlockKind::Heading(level));
+                    let text = raw[start..end].trim_end();
+                    // a closing run of #s needs a space before it, so "# Learning C#" keeps its #
+                    let text = match text.trim_end_matches('#') {
+                        "" => "",
+                        stripped if stripped.ends_with([' ', '\t']) => stripped.trim_end(),
+                        _ => text,
+                    };
+                    inline_markdown(&mut builder, raw, start, start + text.len());
+                    builder.close();
+                    continue;
+                }
+                Some(BlockKind::Quote) if builder.current == Some(BlockKind::Quote) => (),
+                Some(kind) => builder.open(kind),
+                None if builder.current.is_none() => builder.open(BlockKind::Paragraph),
+                // lazy continuation of the open block
+                None => (),
+            }
+            inline_markdown(&mut builder, raw, start, end);
+            // keep lines apart
+            builder.push(" ", end, end);
+        }
+        builder.close();
+        builder.finish(raw.len())
+    }
+
+    // Map a span of text back to the span of the raw document it came from
+    pub fn raw_span(&self, start: usize, end: usize) -> (usize, usize) {
+        if start >= end {
+            let at = self.spans.get(start).map(|s| s.0).unwrap_or(self.raw_len);
+            return (at, at);
+        }
+        (self.spans[start].0, self.spans[end - 1].1)
+    }
+
+    pub fn block_text(&self, block: &Block) -> &str {
+        &self.text[block.start..block.end]
+    }
+
+    // Lexemize the plain text, with lexeme spans pointing into the raw document
+    pub fn lexemize(&self, ruleset: &RuleSet) -> Vec<Lexeme> {
+        ruleset
+            .lexemize(self.text.clone())
+            .into_iter()
+            .map(|lexeme| self.to_raw(lexeme))
+            .collect()
+    }
+
+    // Lexemize each block separately, with lexeme spans pointing into the raw document
+    pub fn lexemize_blocks(&self, ruleset: &RuleSet) -> Vec<(Block, Vec<Lexeme>)> {
+        self.blocks
+            .iter()
+            .map(|block| {
+                let lexemes = ruleset
+                    .lexemize(self.block_text(block).to_string())
+                    .into_iter()
+                    .map(|lexeme| self.to_raw(lexeme.shift(block.start)))
+                    .collect();
+                (*block, lexemes)
+            })
+            .collect()
+    }
+
+    fn to_raw(&self, lexeme: Lexeme) -> Lexeme {
+        let (start, end) = self.raw_span(lexeme.start, lexeme.end);
+        Lexeme {
+            start,
+            end,
+            ..lexeme
+        }
+    }
+}
+
+struct Builder {
+    text: String,
+    spans: Vec<(usize, usize)>,
+    blocks: Vec<Block>,
+    // kind of the open block
+    current: Option<BlockKind>,
+    current_start: usize,
+    // end of the last raw span pushed
+    raw_pos: usize,
+}
+
+impl Builder {
+    fn new() -> Builder {
+        Builder {
+            text: String::new(),
+            spans: Vec::new(),
+            blocks: Vec::new(),
+            current: None,
+            current_start: 0,
+            raw_pos: 0,
+        }
+    }
+
+    // Push text that was produced by raw[raw_start..raw_end]
+    // If the text is a verbatim copy, each byte maps to its own raw byte
+    fn push(&mut self, string: &str, raw_start: usize, raw_end: usize) {
+        if self.current.is_none() {
+            self.open(BlockKind::Paragraph);
+        }
+        let verbatim = raw_end - raw_start == string.len();
+        for i in 0..string.len() {
+            if verbatim {
+                self.spans.push((raw_start + i, raw_start + i + 1));
+            } else {
+                self.spans.push((raw_start, raw_end));
+            }
+        }
+        self.text.push_str(string);
+        self.raw_pos = raw_end;
+    }
+
+    // Push the character or entity at raw[i..], returning how many bytes were consumed
+    fn push_char_or_entity(&mut self, raw: &str, i: usize) -> usize {
+        let rest = &raw[i..];
+        if let Some((c, len)) = parse_entity(rest) {
+            self.push(c.encode_utf8(&mut [0; 4]), i, i + len);
+            return len;
+        }
+        let len = rest.chars().next().unwrap().len_utf8();
+        self.push(&rest[..len], i, i + len);
+        len
+    }
+
+    fn open(&mut self, kind: BlockKind) {
+        self.close();
+        if !self.text.is_empty() {
+            self.text.push('\n');
+            self.spans.push((self.raw_pos, self.raw_pos));
+        }
+        self.current = Some(kind);
+        self.current_start = self.text.len();
+    }
+
+    // Blocks with no visible text are dropped
+    fn close(&mut self) {
+        if let Some(kind) = self.current.take() {
+            let body = &self.text[self.current_start..];
+            let start = self.current_start + body.len() - body.trim_start().len();
+            let end = self.current_start + body.trim_end().len();
+            if start < end {
+                self.blocks.push(Block { kind, start, end });
+            }
+        }
+    }
+
+    fn finish(self, raw_len: usize) -> Document {
+        Document {
+            text: self.text,
+            blocks: self.blocks,
+            spans: self.spans,
+            raw_len,
+        }
+    }
+}
+
+// Push the inline text of raw[start..end], dropping markdown syntax
+fn inline_markdown(builder: &mut Builder, raw: &str, start: usize, end: usize) {
+    let mut i = start;
+    while i < end {
+        let rest = &raw[i..end];
+        let c = rest.chars().next().unwrap();
+        match c {
+            '\\' if rest[1..].starts_with(|n: char| n.is_ascii_punctuation()) => {
+                builder.push(&rest[1..2], i + 1, i + 2);
+                i += 2;
+            }
+            '`' => {
+                let run = rest.len() - rest.trim_start_matches('`').len();
+                let fence = &rest[..run];
+                match rest[run..].find(fence) {
+                    Some(close) => {
+                        let body = i + run;
+                        builder.push(&raw[body..body + close], body, body + close);
+                        i = body + close + run;
+                    }
+                    None => {
+                        builder.push(fence, i, i + run);
+                        i += run;
+                    }
+                }
+            }
+            '!' | '[' if rest.starts_with("![") || c == '[' => {
+                let open = if c == '!' { i + 1 } else { i };
+                match parse_link(raw, open, end) {
+                    Some((text_end, link_end)) => {
+                        inline_markdown(builder, raw, open + 1, text_end);
+                        i = link_end;
+                    }
+                    None => {
+                        builder.push(&rest[..1], i, i + 1);
+                        i += 1;
+                    }
+                }
+            }
+            '<' => {
+                // autolinks keep their target, other inline html is dropped
+                let autolink = rest.find('>').filter(|close| {
+                    let target = &rest[1..*close];
+                    (target.contains("://")
+                        || target.starts_with("mailto:")
+                        || target.contains('@'))
+                        && !target.contains(char::is_whitespace)
+                });
+                if let Some(close) = autolink {
+                    builder.push(&rest[1..close], i + 1, i + close);
+                    i += close + 1;
+                } else if let Some(tag) = parse_tag(rest) {
+                    i += tag.len;
+                } else {
+                    i += builder.push_char_or_entity(raw, i);
+                }
+            }
+            '*' | '_' | '~' => {
+                let run = rest.len() - rest.trim_start_matches(c).len();
+                let before = raw[..i].chars().next_back();
+                let after = rest[run..].chars().next();
+                let flanking = after.is_some_and(|a| !a.is_whitespace())
+                    || before.is_some_and(|b| !b.is_whitespace());
+                // snake_case and 2~3 stay as they are
+                let intraword = before.is_some_and(char::is_alphanumeric)
+                    && after.is_some_and(char::is_alphanumeric);
+                let marker = match c {
+                    '~' => run == 2,
+                    '_' => !intraword,
+                    _ => true,
+                };
+                if !(flanking && marker) {
+                    builder.push(&rest[..run], i, i + run);
+                }
+                i += run;
+            }
+            '|' => {
+                // table cell separator
+                builder.push(" ", i, i + 1);
+                i += 1;
+            }
+            _ => i += builder.push_char_or_entity(raw, i),
+        }
+    }
+}
+
+// Given raw[open] == '[', find a link [text](target) or [text][ref]
+// Returns the end of the text, and the end of the whole link
+fn parse_link(raw: &str, open: usize, end: usize) -> Option<(usize, usize)> {
+    let text_end = open + matching(&raw[open..end], '[', ']')?;
+    let after = text_end + 1;
+    match raw[after..end].chars().next() {
+        Some('(') => Some((text_end, after + matching(&raw[after..end], '(', ')')? + 1)),
+        Some('[') => Some((text_end, after + matching(&raw[after..end], '[', ']')? + 1)),
+        _ => None,
+    }
+}
+
+// Index of the bracket closing the one string starts with
+fn matching(string: &str, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in string.char_indices() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+// Thematic breaks (---, ***, ___) and setext underlines (===)
+fn is_rule(line: &str) -> bool {
+    let c = line.chars().next().unwrap();
+    "-*_=".contains(c)
+        && line.chars().filter(|x| *x == c).count() >= 3
+        && line.chars().all(|x| x == c || x == ' ' || x == '\t')
+}
+
+// Length of an atx heading marker (### ) and the heading level
+fn heading_marker(line: &str) -> Option<(usize, u8)> {
+    let level = line.len() - line.trim_start_matches('#').len();
+    let rest = &line[level..];
+    if (1..=6).contains(&level) && (rest.is_empty() || rest.starts_with(' ')) {
+        Some((line.len() - rest.trim_start().len(), level as u8))
+    } else {
+        None
+    }
+}
+
+// Length of a list item marker (-, *, +, 1., 1))
+fn list_marker(line: &str) -> Option<usize> {
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let marker = match digits {
+        0 if line.starts_with(&['-', '*', '+'][..]) => 1,
+        1..=9 if line[digits..].starts_with(&['.', ')'][..]) => digits + 1,
+        _ => return None,
+    };
+    let rest = &line[marker..];
+    if rest.starts_with(' ') || rest.starts_with('\t') {
+        Some(line.len() - rest.trim_start().len())
+    } else {
+        None
+    }
+}
+
+struct Tag {
+    name: String, // lowercase
+    closing: bool,
+    len: usize,
+}
+
+// Parse an html tag (or doctype) at the start of string
+// A < that doesn't start a tag (a < b) isn't one
+fn parse_tag(string: &str) -> Option<Tag> {
+    let rest = string.strip_prefix('<')?;
+    let (closing, rest) = match rest.strip_prefix('/') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    let name_len = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '!' || c == '?'))
+        .unwrap_or(rest.len());
+    if name_len == 0 || !rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '!' || c == '?')
+    {
+        return None;
+    }
+    // find the closing >, skipping over quoted attribute values
+    let mut quote = None;
+    for (i, c) in string.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, '>') => {
+                return Some(Tag {
+                    name: rest[..name_len].to_ascii_lowercase(),
+                    closing,
+                    len: i + 1,
+                })
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+// The length of a script or style body, up to and including its closing tag
+// The body isn't html, so it is searched for the closing tag rather than parsed: parse_tag
+// would take the "<b" of "a<b" for a tag, and a quote after it could run past the closing tag
+fn raw_text_len(string: &str, name: &str) -> usize {
+    let lower = string.to_ascii_lowercase();
+    let close = format!("</{}", name);
+    let mut from = 0;
+    while let Some(at) = lower[from..].find(&close) {
+        let after = from + at + close.len();
+        if lower[after..].starts_with(|c: char| c == '>' || c == '/' || c.is_ascii_whitespace()) {
+            return string[after..]
+                .find('>')
+                .map_or(string.len(), |end| after + end + 1);
+        }
+        from = after;
+    }
+    string.len()
+}
+
+// Parse an html character reference at the start of string
+fn parse_entity(string: &str) -> Option<(char, usize)> {
+    let rest = string.strip_prefix('&')?;
+    let end = rest.find(';').filter(|end| *end <= 10)?;
+    let name = &rest[..end];
+    let c = if let Some(num) = name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+        char::from_u32(u32::from_str_radix(num, 16).ok()?)?
+    } else if let Some(num) = name.strip_prefix('#') {
+        char::from_u32(num.parse().ok()?)?
+    } else {
+        match name {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            "nbsp" => '\u{a0}',
+            "ndash" => '–',
+            "mdash" => '—',
+            "hellip" => '…',
+            "lsquo" => '‘',
+            "rsquo" => '’',
+            "ldquo" => '“',
+            "rdquo" => '”',
+            "laquo" => '«',
+            "raquo" => '»',
+            "copy" => '©',
+            "reg" => '®',
+            "trade" => '™',
+            "euro" => '€',
+            "pound" => '£',
+            "yen" => '¥',
+            "cent" => '¢',
+            _ => return None,
+        }
+    };
+    Some((c, end + 2))
+}
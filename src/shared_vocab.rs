@@ -0,0 +1,231 @@
+use super::lexemizer::Lexeme;
+use super::vocab::Frequency;
+use super::vocab::Vocab;
+use super::vocab::VocabIndex;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::ops::Deref;
+use std::ops::DerefMut;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::PoisonError;
+use std::sync::RwLock;
+use std::sync::RwLockReadGuard;
+use std::sync::RwLockWriteGuard;
+
+// A vocab shared between threads, so documents can be processed in parallel with the same ids
+// Each thread works through its own VocabHandle, which caches up to CACHE_CAPACITY of the ids
+// it has seen: looking those up again takes no lock. Anything else takes the read lock,
+// and only strings that are new to the vocab take the write lock
+#[derive(Clone)]
+pub struct SharedVocab {
+    inner: Arc<Shared>,
+}
+
+struct Shared {
+    vocab: RwLock<Vocab>,
+    // bumped whenever the vocab is changed in a way that could invalidate cached ids
+    generation: AtomicU64,
+}
+
+impl SharedVocab {
+    pub fn new(vocab: Vocab) -> SharedVocab {
+        SharedVocab {
+            inner: Arc::new(Shared {
+                vocab: RwLock::new(vocab),
+                generation: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    pub fn handle(&self) -> VocabHandle {
+        VocabHandle {
+            shared: self.clone(),
+            cache: HashMap::new(),
+            generation: self.inner.generation.load(Ordering::Acquire),
+            frequencies: HashMap::new(),
+            documents: 0,
+        }
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, Vocab> {
+        self.inner.vocab.read().unwrap()
+    }
+
+    // Exclusive access, e.g. for pruning. Every handle drops its cache afterwards
+    pub fn write(&self) -> VocabWriteGuard<'_> {
+        VocabWriteGuard {
+            guard: self.inner.vocab.write().unwrap(),
+            generation: &self.inner.generation,
+        }
+    }
+
+    // Returns the vocab if this is the last reference to it
+    pub fn into_inner(self) -> Option<Vocab> {
+        Arc::try_unwrap(self.inner)
+            .ok()
+            .map(|shared| shared.vocab.into_inner().unwrap())
+    }
+}
+
+// Bumps the generation when dropped, once the vocab has been changed but before the lock is
+// released, so a handle can't cache an id from before the change after seeing the new generation
+pub struct VocabWriteGuard<'a> {
+    guard: RwLockWriteGuard<'a, Vocab>,
+    generation: &'a AtomicU64,
+}
+
+impl Deref for VocabWriteGuard<'_> {
+    type Target = Vocab;
+
+    fn deref(&self) -> &Vocab {
+        &self.guard
+    }
+}
+
+impl DerefMut for VocabWriteGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Vocab {
+        &mut self.guard
+    }
+}
+
+impl Drop for VocabWriteGuard<'_> {
+    fn drop(&mut self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+// Once a handle has cached this many ids, it starts again with an empty cache
+pub const CACHE_CAPACITY: usize = 1 << 16;
+
+// One thread's view of a SharedVocab. Not meant to be shared itself
+// Frequencies counted through a handle are kept locally until flush (or drop)
+pub struct VocabHandle {
+    shared: SharedVocab,
+    cache: HashMap<Vec<u8>, VocabIndex>,
+    generation: u64,
+    frequencies: HashMap<VocabIndex, Frequency>,
+    documents: u64,
+}
+
+impl VocabHandle {
+    fn check_generation(&mut self) {
+        let generation = self.shared.inner.generation.load(Ordering::Acquire);
+        if generation != self.generation {
+            self.cache.clear();
+            self.generation = generation;
+        }
+    }
+
+    // Returns the index of value, adding it to the shared vocab if it isn't present
+    // None if it needed adding, but every id has been used
+    pub fn intern(&mut self, value: &[u8]) -> Option<VocabIndex> {
+        self.check_generation();
+        if let Some(index) = self.cache.get(value) {
+            return Some(*index);
+        }
+        let found = self.shared.read().get_id_by_str(value);
+        let index = match found {
+            Some(index) => index,
+            // another thread may have added it between the two locks, so intern, not add
+            None => self.shared.inner.vocab.write().unwrap().intern(value)?,
+        };
+        self.cache_id(value, index);
+        Some(index)
+    }
+
+    // Returns the index of value, or OOV if it isn't present
+    pub fn lookup(&mut self, value: &[u8]) -> VocabIndex {
+        self.check_generation();
+        if let Some(index) = self.cache.get(value) {
+            return *index;
+        }
+        let found = self.shared.read().get_id_by_str(value);
+        match found {
+            Some(index) => {
+                self.cache_id(value, index);
+                index
+            }
+            None => VocabIndex::OOV,
+        }
+    }
+
+    fn cache_id(&mut self, value: &[u8], index: VocabIndex) {
+        if self.cache.len() >= CACHE_CAPACITY {
+            self.cache.clear();
+        }
+        self.cache.insert(value.to_vec(), index);
+    }
+
+    // Lexemes that can't be added become OOV
+    pub fn intern_lexemes(&mut self, lexemes: Vec<Lexeme>) -> Vec<Lexeme<VocabIndex>> {
+        lexemes
+            .into_iter()
+            .map(|lexeme| lexeme.map(|value| self.intern(&value).unwrap_or(VocabIndex::OOV)))
+            .collect()
+    }
+
+    pub fn lookup_lexemes(&mut self, lexemes: Vec<Lexeme>) -> Vec<Lexeme<VocabIndex>> {
+        lexemes
+            .into_iter()
+            .map(|lexeme| lexeme.map(|value| self.lookup(&value)))
+            .collect()
+    }
+
+    // Intern and count the lexemes of one document
+    // Lexemes that can't be added aren't counted
+    pub fn count_document(&mut self, lexemes: &[Lexeme]) {
+        let mut seen = HashSet::new();
+        for lexeme in lexemes {
+            let index = match self.intern(&lexeme.value) {
+                Some(index) => index,
+                None => continue,
+            };
+            let frequency = self.frequencies.entry(index).or_default();
+            frequency.count += 1;
+            if seen.insert(index) {
+                frequency.doc_count += 1;
+            }
+        }
+        self.documents += 1;
+    }
+
+    // Add the frequencies counted so far to the shared vocab
+    pub fn flush(&mut self) {
+        if self.frequencies.is_empty() && self.documents == 0 {
+            return;
+        }
+        let mut vocab = self.shared.inner.vocab.write().unwrap();
+        add_counts(&mut vocab, &mut self.frequencies, &mut self.documents);
+    }
+}
+
+impl Drop for VocabHandle {
+    // A thread that panicked while holding the lock poisons it. Panicking here as well would
+    // abort if this handle is being dropped while unwinding, so the counts go in regardless
+    fn drop(&mut self) {
+        if self.frequencies.is_empty() && self.documents == 0 {
+            return;
+        }
+        let mut vocab = self
+            .shared
+            .inner
+            .vocab
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        add_counts(&mut vocab, &mut self.frequencies, &mut self.documents);
+    }
+}
+
+fn add_counts(
+    vocab: &mut Vocab,
+    frequencies: &mut HashMap<VocabIndex, Frequency>,
+    documents: &mut u64,
+) {
+    for (index, frequency) in frequencies.drain() {
+        vocab.add_frequency(index, frequency);
+    }
+    vocab.add_documents(*documents);
+    *documents = 0;
+}
@@ -0,0 +1,486 @@
+use super::invalid_data;
+use super::lexemizer::Lexeme;
+use super::vocab::Vocab;
+use super::vocab::VocabIndex;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::path::Path;
+
+// Subword tokenization of lexemes, with a fixed size piece vocabulary
+// Both algorithms learn merges of adjacent pieces from the lexeme frequencies in a Vocab
+//   Bpe merges the most frequent pair. Pieces that end a lexeme are marked (like "</w>")
+//   WordPiece merges the pair with the best freq(ab) / (freq(a) * freq(b)). Pieces that
+//   continue a lexeme are marked (like "##")
+// Lexemes are ascii, so the base alphabet is bytes
+
+#[derive(Debug, Clone, Copy, std::cmp::Eq, std::cmp::PartialEq)]
+pub enum Algorithm {
+    Bpe,
+    WordPiece,
+}
+
+#[derive(Debug, Clone, std::hash::Hash, std::cmp::Eq, std::cmp::PartialEq)]
+pub struct Piece {
+    pub value: Vec<u8>,
+    pub word_start: bool, // only used by WordPiece
+    pub word_end: bool,   // only used by Bpe
+}
+
+// Id of the piece standing in for bytes that can't be encoded
+pub const UNKNOWN: u32 = 0;
+
+const UNKNOWN_VALUE: &[u8] = b"[UNK]";
+const HEADER: &str = "yogurt-subwords 1";
+
+pub struct Subwords {
+    algorithm: Algorithm,
+    pieces: Vec<Piece>,
+    ids: HashMap<Piece, u32>,
+    // (left, right) -> (rank, merged)
+    merges: HashMap<(u32, u32), (usize, u32)>,
+    merge_order: Vec<(u32, u32)>,
+}
+
+impl Subwords {
+    fn new(algorithm: Algorithm) -> Subwords {
+        let mut subwords = Subwords {
+            algorithm,
+            pieces: Vec::new(),
+            ids: HashMap::new(),
+            merges: HashMap::new(),
+            merge_order: Vec::new(),
+        };
+        subwords.add_piece(Piece {
+            value: UNKNOWN_VALUE.to_vec(),
+            word_start: true,
+            word_end: true,
+        });
+        subwords
+    }
+
+    fn add_piece(&mut self, piece: Piece) -> u32 {
+        if let Some(id) = self.ids.get(&piece) {
+            return *id;
+        }
+        let id = self.pieces.len() as u32;
+        self.ids.insert(piece.clone(), id);
+        self.pieces.push(piece);
+        id
+    }
+
+    fn add_merge(&mut self, left: u32, right: u32) -> u32 {
+        let (a, b) = (&self.pieces[left as usize], &self.pieces[right as usize]);
+        let merged = self.add_piece(Piece {
+            value: [a.value.as_slice(), b.value.as_slice()].concat(),
+            word_start: a.word_start,
+            word_end: b.word_end,
+        });
+        self.merges
+            .insert((left, right), (self.merge_order.len(), merged));
+        self.merge_order.push((left, right));
+        merged
+    }
+
+    // The base piece for byte c at position i of a lexeme of length len
+    fn byte_piece(&self, c: u8, i: usize, len: usize) -> Piece {
+        Piece {
+            value: vec![c],
+            word_start: self.algorithm == Algorithm::WordPiece && i == 0,
+            word_end: self.algorithm == Algorithm::Bpe && i + 1 == len,
+        }
+    }
+
+    pub fn train_bpe(vocab: &Vocab, vocab_size: usize, min_frequency: u64) -> Subwords {
+        Subwords::train(Algorithm::Bpe, vocab, vocab_size, min_frequency)
+    }
+
+    pub fn train_wordpiece(vocab: &Vocab, vocab_size: usize, min_frequency: u64) -> Subwords {
+        Subwords::train(Algorithm::WordPiece, vocab, vocab_size, min_frequency)
+    }
+
+    // Learn merges until there are vocab_size pieces (or nothing is left to merge)
+    // Only entries counted at least min_frequency times (see Vocab::count_corpus) are used
+    pub fn train(
+        algorithm: Algorithm,
+        vocab: &Vocab,
+        vocab_size: usize,
+        min_frequency: u64,
+    ) -> Subwords {
+        let mut subwords = Subwords::new(algorithm);
+        let mut words: Vec<(Vec<u32>, u64)> = Vec::new();
+        for (index, value) in vocab.iter() {
+            let count = vocab.frequency(index).count;
+            if count == 0 || count < min_frequency || value.is_empty() {
+                continue;
+            }
+            let symbols = value
+                .iter()
+                .enumerate()
+                .map(|(i, c)| subwords.add_piece(subwords.byte_piece(*c, i, value.len())))
+                .collect();
+            words.push((symbols, count));
+        }
+
+        let mut pairs: HashMap<(u32, u32), u64> = HashMap::new();
+        let mut pair_words: HashMap<(u32, u32), HashSet<usize>> = HashMap::new();
+        let mut symbol_counts: HashMap<u32, u64> = HashMap::new();
+        for (w, (symbols, count)) in words.iter().enumerate() {
+            count_word(symbols, *count as i64, &mut pairs, &mut symbol_counts);
+            for pair in symbols.windows(2) {
+                pair_words.entry((pair[0], pair[1])).or_default().insert(w);
+            }
+        }
+
+        while subwords.pieces.len() < vocab_size {
+            let best = pairs
+                .iter()
+                .map(|(pair, count)| {
+                    let score = match algorithm {
+                        Algorithm::Bpe => *count as f64,
+                        Algorithm::WordPiece => {
+                            *count as f64
+                                / (symbol_counts[&pair.0] as f64 * symbol_counts[&pair.1] as f64)
+                        }
+                    };
+                    (*pair, score)
+                })
+                // highest score, then lowest ids, so training is deterministic
+                .max_by(|a, b| a.1.total_cmp(&b.1).then(b.0.cmp(&a.0)));
+            let (left, right) = match best {
+                Some((pair, _)) => pair,
+                None => break,
+            };
+            let merged = subwords.add_merge(left, right);
+            for w in pair_words.remove(&(left, right)).unwrap_or_default() {
+                let (symbols, count) = &mut words[w];
+                count_word(symbols, -(*count as i64), &mut pairs, &mut symbol_counts);
+                *symbols = apply_merge(symbols, left, right, merged);
+                count_word(symbols, *count as i64, &mut pairs, &mut symbol_counts);
+                for pair in symbols.windows(2) {
+                    pair_words.entry((pair[0], pair[1])).or_default().insert(w);
+                }
+            }
+        }
+        subwords
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn len(&self) -> usize {
+        self.pieces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pieces.is_empty()
+    }
+
+    pub fn piece(&self, id: u32) -> Option<&Piece> {
+        self.pieces.get(id as usize)
+    }
+
+    pub fn id(&self, piece: &Piece) -> Option<u32> {
+        self.ids.get(piece).copied()
+    }
+
+    // Split one lexeme into piece ids
+    pub fn encode(&self, value: &[u8]) -> Vec<u32> {
+        if value.is_empty() {
+            return Vec::new();
+        }
+        match self.algorithm {
+            Algorithm::Bpe => self.encode_bpe(value),
+            Algorithm::WordPiece => self.encode_wordpiece(value),
+        }
+    }
+
+    // Apply merges lowest rank first
+    fn encode_bpe(&self, value: &[u8]) -> Vec<u32> {
+        let mut symbols: Vec<u32> = Vec::with_capacity(value.len());
+        for (i, c) in value.iter().enumerate() {
+            match self.id(&self.byte_piece(*c, i, value.len())) {
+                Some(id) => symbols.push(id),
+                // the whole lexeme is unknown, so that decoding keeps lexeme boundaries
+                None => return vec![UNKNOWN],
+            }
+        }
+        loop {
+            let best = symbols
+                .windows(2)
+                .filter_map(|pair| self.merges.get(&(pair[0], pair[1])))
+                .min_by_key(|(rank, _)| *rank);
+            match best {
+                Some((rank, merged)) => {
+                    let (left, right) = self.merge_order[*rank];
+                    symbols = apply_merge(&symbols, left, right, *merged);
+                }
+                None => return symbols,
+            }
+        }
+    }
+
+    // Greedy longest match first
+    fn encode_wordpiece(&self, value: &[u8]) -> Vec<u32> {
+        let mut ids = Vec::new();
+        let mut start = 0;
+        while start < value.len() {
+            let found = (start + 1..=value.len()).rev().find_map(|end| {
+                self.id(&Piece {
+                    value: value[start..end].to_vec(),
+                    word_start: start == 0,
+                    word_end: false,
+                })
+                .map(|id| (id, end))
+            });
+            match found {
+                Some((id, end)) => {
+                    ids.push(id);
+                    start = end;
+                }
+                None => return vec![UNKNOWN],
+            }
+        }
+        ids
+    }
+
+    pub fn encode_lexemes(&self, lexemes: &[Lexeme]) -> Vec<u32> {
+        lexemes
+            .iter()
+            .flat_map(|lexeme| self.encode(&lexeme.value))
+            .collect()
+    }
+
+    // Lexemes that aren't in the vocab are encoded as UNKNOWN
+    pub fn encode_interned(&self, vocab: &Vocab, lexemes: &[Lexeme<VocabIndex>]) -> Vec<u32> {
+        lexemes
+            .iter()
+            .flat_map(|lexeme| match vocab.get(lexeme.value) {
+                Some(value) => self.encode(value),
+                None => vec![UNKNOWN],
+            })
+            .collect()
+    }
+
+    // Join piece ids back into lexeme values
+    pub fn decode(&self, ids: &[u32]) -> Vec<Vec<u8>> {
+        let mut lexemes = Vec::new();
+        let mut current = Vec::new();
+        for id in ids {
+            let piece = match self.piece(*id) {
+                Some(piece) => piece,
+                None => &self.pieces[UNKNOWN as usize],
+            };
+            let starts =
+                *id == UNKNOWN || (self.algorithm == Algorithm::WordPiece && piece.word_start);
+            if starts && !current.is_empty() {
+                lexemes.push(std::mem::take(&mut current));
+            }
+            current.extend_from_slice(&piece.value);
+            let ends = *id == UNKNOWN || (self.algorithm == Algorithm::Bpe && piece.word_end);
+            if ends {
+                lexemes.push(std::mem::take(&mut current));
+            }
+        }
+        if !current.is_empty() {
+            lexemes.push(current);
+        }
+        lexemes
+    }
+
+    // Text format, so that it is easy to read into other tools:
+    //   yogurt-subwords 1
+    //   algorithm bpe|wordpiece
+    //   pieces <count>
+    //   <id> <flags> <value>      flags is "-", or some of "s" (word_start) and "e" (word_end)
+    //   merges <count>
+    //   <left id> <right id>      in rank order
+    // Values are escaped, with \\ for \ and \xHH for bytes that aren't printable
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut file = io::BufWriter::new(File::create(path)?);
+        self.write_to(&mut file)?;
+        file.flush()
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "{}", HEADER)?;
+        let algorithm = match self.algorithm {
+            Algorithm::Bpe => "bpe",
+            Algorithm::WordPiece => "wordpiece",
+        };
+        writeln!(writer, "algorithm {}", algorithm)?;
+        writeln!(writer, "pieces {}", self.pieces.len())?;
+        for (id, piece) in self.pieces.iter().enumerate() {
+            let mut flags = String::new();
+            if piece.word_start {
+                flags.push('s');
+            }
+            if piece.word_end {
+                flags.push('e');
+            }
+            if flags.is_empty() {
+                flags.push('-');
+            }
+            writeln!(writer, "{} {} {}", id, flags, escape(&piece.value))?;
+        }
+        writeln!(writer, "merges {}", self.merge_order.len())?;
+        for (left, right) in self.merge_order.iter() {
+            writeln!(writer, "{} {}", left, right)?;
+        }
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Subwords> {
+        Subwords::read_from(BufReader::new(File::open(path)?))
+    }
+
+    pub fn read_from(reader: impl BufRead) -> io::Result<Subwords> {
+        let mut lines = reader.lines();
+        let mut next = || -> io::Result<String> {
+            lines
+                .next()
+                .unwrap_or_else(|| Err(invalid_data("subword file is truncated")))
+        };
+        if next()? != HEADER {
+            return Err(invalid_data("not a subword file, or unsupported version"));
+        }
+        let algorithm = match next()?.as_str() {
+            "algorithm bpe" => Algorithm::Bpe,
+            "algorithm wordpiece" => Algorithm::WordPiece,
+            _ => return Err(invalid_data("unknown subword algorithm")),
+        };
+        let mut subwords = Subwords {
+            algorithm,
+            pieces: Vec::new(),
+            ids: HashMap::new(),
+            merges: HashMap::new(),
+            merge_order: Vec::new(),
+        };
+        let count = parse_count(&next()?, "pieces")?;
+        for id in 0..count {
+            let line = next()?;
+            let mut fields = line.splitn(3, ' ');
+            let (read_id, flags, value) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(read_id), Some(flags), Some(value)) => (read_id, flags, value),
+                _ => return Err(invalid_data("bad piece line")),
+            };
+            if read_id.parse::<usize>().ok() != Some(id) {
+                return Err(invalid_data("pieces are out of order"));
+            }
+            let piece = Piece {
+                value: unescape(value)?,
+                word_start: flags.contains('s'),
+                word_end: flags.contains('e'),
+            };
+            if subwords.add_piece(piece) != id as u32 {
+                return Err(invalid_data("duplicate piece"));
+            }
+        }
+        let pieces = subwords.pieces.len();
+        let count = parse_count(&next()?, "merges")?;
+        for _ in 0..count {
+            let line = next()?;
+            let ids: Vec<u32> = line
+                .split(' ')
+                .map(|id| id.parse::<u32>())
+                .collect::<Result<_, _>>()
+                .map_err(|_| invalid_data("bad merge line"))?;
+            if ids.len() != 2 || ids.iter().any(|id| *id as usize >= subwords.pieces.len()) {
+                return Err(invalid_data("bad merge line"));
+            }
+            subwords.add_merge(ids[0], ids[1]);
+        }
+        if subwords.pieces.len() != pieces {
+            return Err(invalid_data(
+                "merges produce pieces that aren't in the file",
+            ));
+        }
+        Ok(subwords)
+    }
+}
+
+// Add (or with a negative count, remove) the pairs and symbols of a word
+fn count_word(
+    symbols: &[u32],
+    count: i64,
+    pairs: &mut HashMap<(u32, u32), u64>,
+    symbol_counts: &mut HashMap<u32, u64>,
+) {
+    for pair in symbols.windows(2) {
+        let pair = (pair[0], pair[1]);
+        let total = pairs.entry(pair).or_default();
+        *total = (*total as i64 + count) as u64;
+        if *total == 0 {
+            pairs.remove(&pair);
+        }
+    }
+    for symbol in symbols {
+        let total = symbol_counts.entry(*symbol).or_default();
+        *total = (*total as i64 + count) as u64;
+    }
+}
+
+// Replace every (left, right) in symbols with merged, left to right
+fn apply_merge(symbols: &[u32], left: u32, right: u32, merged: u32) -> Vec<u32> {
+    let mut result = Vec::with_capacity(symbols.len());
+    let mut i = 0;
+    while i < symbols.len() {
+        if i + 1 < symbols.len() && symbols[i] == left && symbols[i + 1] == right {
+            result.push(merged);
+            i += 2;
+        } else {
+            result.push(symbols[i]);
+            i += 1;
+        }
+    }
+    result
+}
+
+fn escape(value: &[u8]) -> String {
+    let mut escaped = String::new();
+    for c in value {
+        match c {
+            b'\\' => escaped.push_str("\\\\"),
+            b'!'..=b'~' => escaped.push(*c as char),
+            _ => escaped.push_str(&format!("\\x{:02x}", c)),
+        }
+    }
+    escaped
+}
+
+fn unescape(value: &str) -> io::Result<Vec<u8>> {
+    let bytes = value.as_bytes();
+    let mut value = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if bytes.get(i + 1) == Some(&b'\\') => {
+                value.push(b'\\');
+                i += 2;
+            }
+            b'\\' if bytes.get(i + 1) == Some(&b'x') && i + 4 <= bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 2..i + 4]).ok();
+                let c = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                value.push(c.ok_or_else(|| invalid_data("bad escape"))?);
+                i += 4;
+            }
+            b'\\' => return Err(invalid_data("bad escape")),
+            c => {
+                value.push(c);
+                i += 1;
+            }
+        }
+    }
+    Ok(value)
+}
+
+fn parse_count(line: &str, name: &str) -> io::Result<usize> {
+    line.strip_prefix(name)
+        .and_then(|count| count.trim().parse().ok())
+        .ok_or_else(|| invalid_data("bad count line"))
+}
@@ -1,8 +1,39 @@
+use super::alignment::Alignment;
+use super::conllu;
+use super::decoder;
+use super::decoder::Decoder;
+use super::decoder::Scorer;
+use super::decoder::Update;
+use super::features;
+use super::features::Affix;
+use super::features::Clusters;
+use super::features::Feature;
+use super::features::Template;
+use super::invalid_data;
+use super::lexemizer::Case;
 use super::lexemizer::Lexeme;
+use super::lexemizer::RuleSet;
+use super::universal::UniversalPos;
+use super::vocab::Vocab;
+use super::Fnv;
 use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Debug;
+use std::fs::File;
+use std::hash::BuildHasherDefault;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
 
 // Definitions from here:
 // https://web.stanford.edu/~jurafsky/slp3/8.pdf
+#[allow(non_camel_case_types)]
 #[derive(Debug, std::hash::Hash, Clone, Copy, std::cmp::Eq, std::cmp::PartialEq)]
 pub enum PartOfSpeech {
     CC,      // coordinating conjunction
@@ -49,52 +80,1850 @@ pub enum PartOfSpeech {
     RPAREN,  // )
     COMMA,   // ,
     ENDPUNC, // .
-    MIDPUNC, // ;
+    MIDPUNC, // ; : and dashes
+    // OntoNotes and the English Web Treebank
+    NFP,  // superfluous punctuation, e.g. "***", emoticons
+    ADD,  // email or web address
+    AFX,  // affix written apart from its word, e.g. "pre" in "pre and post"
+    HYPH, // hyphen
+    XX,   // typo or unknown
+    GW,   // part of a word written in pieces, e.g. "some" in "some one"
+    // spellings of the tags above that some corpora use, e.g. CoNLL 2000 and 2003
+    LPAREN_RAW, // "(" rather than "-LRB-"
+    RPAREN_RAW, // ")" rather than "-RRB-"
+    QUOTE,      // '"' rather than "``" or "''"
+}
+
+impl PartOfSpeech {
+    pub const COUNT: usize = 54;
+
+    // In declaration order, so PartOfSpeech::ALL[tag as usize] == tag
+    pub const ALL: [PartOfSpeech; PartOfSpeech::COUNT] = [
+        PartOfSpeech::CC,
+        PartOfSpeech::CD,
+        PartOfSpeech::DT,
+        PartOfSpeech::EX,
+        PartOfSpeech::FW,
+        PartOfSpeech::IN,
+        PartOfSpeech::JJ,
+        PartOfSpeech::JJR,
+        PartOfSpeech::JJS,
+        PartOfSpeech::LS,
+        PartOfSpeech::MD,
+        PartOfSpeech::NN,
+        PartOfSpeech::NNS,
+        PartOfSpeech::NNP,
+        PartOfSpeech::NNPS,
+        PartOfSpeech::PDT,
+        PartOfSpeech::POS,
+        PartOfSpeech::PRP,
+        PartOfSpeech::PRP_S,
+        PartOfSpeech::RB,
+        PartOfSpeech::RBR,
+        PartOfSpeech::RBS,
+        PartOfSpeech::RP,
+        PartOfSpeech::SYM,
+        PartOfSpeech::TO,
+        PartOfSpeech::UH,
+        PartOfSpeech::VB,
+        PartOfSpeech::VBD,
+        PartOfSpeech::VBG,
+        PartOfSpeech::VBN,
+        PartOfSpeech::VBP,
+        PartOfSpeech::VBZ,
+        PartOfSpeech::WDT,
+        PartOfSpeech::WP,
+        PartOfSpeech::WP_S,
+        PartOfSpeech::WRB,
+        PartOfSpeech::DOLLAR,
+        PartOfSpeech::HASH,
+        PartOfSpeech::LQUOTE,
+        PartOfSpeech::RQUOTE,
+        PartOfSpeech::LPAREN,
+        PartOfSpeech::RPAREN,
+        PartOfSpeech::COMMA,
+        PartOfSpeech::ENDPUNC,
+        PartOfSpeech::MIDPUNC,
+        PartOfSpeech::NFP,
+        PartOfSpeech::ADD,
+        PartOfSpeech::AFX,
+        PartOfSpeech::HYPH,
+        PartOfSpeech::XX,
+        PartOfSpeech::GW,
+        PartOfSpeech::LPAREN_RAW,
+        PartOfSpeech::RPAREN_RAW,
+        PartOfSpeech::QUOTE,
+    ];
+
+    // Penn Treebank tags as they appear in treebanks, e.g. "PRP$", "$", "-LRB-"
+    // Every spelling is its own tag, so treebank(from_treebank(tag)) == tag
+    pub fn from_treebank(tag: &str) -> Option<PartOfSpeech> {
+        use PartOfSpeech::*;
+        Some(match tag {
+            "CC" => CC,
+            "CD" => CD,
+            "DT" => DT,
+            "EX" => EX,
+            "FW" => FW,
+            "IN" => IN,
+            "JJ" => JJ,
+            "JJR" => JJR,
+            "JJS" => JJS,
+            "LS" => LS,
+            "MD" => MD,
+            "NN" => NN,
+            "NNS" => NNS,
+            "NNP" => NNP,
+            "NNPS" => NNPS,
+            "PDT" => PDT,
+            "POS" => POS,
+            "PRP" => PRP,
+            "PRP$" => PRP_S,
+            "RB" => RB,
+            "RBR" => RBR,
+            "RBS" => RBS,
+            "RP" => RP,
+            "SYM" => SYM,
+            "TO" => TO,
+            "UH" => UH,
+            "VB" => VB,
+            "VBD" => VBD,
+            "VBG" => VBG,
+            "VBN" => VBN,
+            "VBP" => VBP,
+            "VBZ" => VBZ,
+            "WDT" => WDT,
+            "WP" => WP,
+            "WP$" => WP_S,
+            "WRB" => WRB,
+            "$" => DOLLAR,
+            "#" => HASH,
+            "``" => LQUOTE,
+            "''" => RQUOTE,
+            "-LRB-" => LPAREN,
+            "-RRB-" => RPAREN,
+            "," => COMMA,
+            "." => ENDPUNC,
+            ":" => MIDPUNC,
+            "NFP" => NFP,
+            "ADD" => ADD,
+            "AFX" => AFX,
+            "HYPH" => HYPH,
+            "XX" => XX,
+            "GW" => GW,
+            "(" => LPAREN_RAW,
+            ")" => RPAREN_RAW,
+            "\"" => QUOTE,
+            _ => return None,
+        })
+    }
+
+    // The inverse of from_treebank, e.g. "PRP$" for PRP_S
+    pub fn treebank(&self) -> &'static str {
+        use PartOfSpeech::*;
+        match self {
+            CC => "CC",
+            CD => "CD",
+            DT => "DT",
+            EX => "EX",
+            FW => "FW",
+            IN => "IN",
+            JJ => "JJ",
+            JJR => "JJR",
+            JJS => "JJS",
+            LS => "LS",
+            MD => "MD",
+            NN => "NN",
+            NNS => "NNS",
+            NNP => "NNP",
+            NNPS => "NNPS",
+            PDT => "PDT",
+            POS => "POS",
+            PRP => "PRP",
+            PRP_S => "PRP$",
+            RB => "RB",
+            RBR => "RBR",
+            RBS => "RBS",
+            RP => "RP",
+            SYM => "SYM",
+            TO => "TO",
+            UH => "UH",
+            VB => "VB",
+            VBD => "VBD",
+            VBG => "VBG",
+            VBN => "VBN",
+            VBP => "VBP",
+            VBZ => "VBZ",
+            WDT => "WDT",
+            WP => "WP",
+            WP_S => "WP$",
+            WRB => "WRB",
+            DOLLAR => "$",
+            HASH => "#",
+            LQUOTE => "``",
+            RQUOTE => "''",
+            LPAREN => "-LRB-",
+            RPAREN => "-RRB-",
+            COMMA => ",",
+            ENDPUNC => ".",
+            MIDPUNC => ":",
+            NFP => "NFP",
+            ADD => "ADD",
+            AFX => "AFX",
+            HYPH => "HYPH",
+            XX => "XX",
+            GW => "GW",
+            LPAREN_RAW => "(",
+            RPAREN_RAW => ")",
+            QUOTE => "\"",
+        }
+    }
+
+    // Universal POS tags are coarser than Penn Treebank ones, so this is lossy:
+    // the form picks between the tags that are easy to tell apart (modals, "to", punctuation)
+    // and otherwise the base tag is used (every VERB is a VB, every NOUN an NN)
+    pub fn from_universal(tag: &str, form: &str) -> Option<PartOfSpeech> {
+        use PartOfSpeech::*;
+        let form = form.to_lowercase();
+        Some(match tag {
+            "ADJ" => JJ,
+            "ADP" | "SCONJ" => IN,
+            "ADV" => RB,
+            "AUX" => match form.as_str() {
+                "can" | "could" | "will" | "would" | "shall" | "should" | "may" | "might"
+                | "must" | "'ll" | "'d" | "wo" | "ca" => MD,
+                _ => VB,
+            },
+            "CCONJ" => CC,
+            "DET" => DT,
+            "INTJ" => UH,
+            "NOUN" => NN,
+            "NUM" => CD,
+            "PART" => match form.as_str() {
+                "to" => TO,
+                "'s" | "'" => POS,
+                "not" | "n't" => RB,
+                _ => RP,
+            },
+            "PRON" => PRP,
+            "PROPN" => NNP,
+            "PUNCT" => match form.as_str() {
+                "," => COMMA,
+                "." | "!" | "?" => ENDPUNC,
+                "-" => HYPH,
+                "(" | "[" | "{" => LPAREN,
+                ")" | "]" | "}" => RPAREN,
+                "``" | "\u{201c}" | "\u{2018}" => LQUOTE,
+                "''" | "\"" | "'" | "\u{201d}" | "\u{2019}" => RQUOTE,
+                "#" => HASH,
+                "$" => DOLLAR,
+                _ => MIDPUNC,
+            },
+            "SYM" => match form.as_str() {
+                "$" => DOLLAR,
+                "#" => HASH,
+                _ => SYM,
+            },
+            "VERB" => VB,
+            "X" => FW,
+            _ => return None,
+        })
+    }
+
+    // The Universal POS tag of a Penn Treebank tag, following the UD English treebanks
+    // Each tag maps to one Universal tag, so the mapping can't look at the word: every VB is
+    // a VERB (even auxiliaries), every IN an ADP (even subordinating conjunctions)
+    pub fn universal(&self) -> UniversalPos {
+        use PartOfSpeech::*;
+        match self {
+            CC => UniversalPos::CCONJ,
+            CD => UniversalPos::NUM,
+            DT | PDT | WDT => UniversalPos::DET,
+            EX | PRP | PRP_S | WP | WP_S => UniversalPos::PRON,
+            FW | LS | ADD | XX | GW => UniversalPos::X,
+            IN | RP => UniversalPos::ADP,
+            JJ | JJR | JJS | AFX => UniversalPos::ADJ,
+            MD => UniversalPos::AUX,
+            NN | NNS => UniversalPos::NOUN,
+            NNP | NNPS => UniversalPos::PROPN,
+            POS | TO => UniversalPos::PART,
+            RB | RBR | RBS | WRB => UniversalPos::ADV,
+            SYM | DOLLAR | HASH => UniversalPos::SYM,
+            UH => UniversalPos::INTJ,
+            VB | VBD | VBG | VBN | VBP | VBZ => UniversalPos::VERB,
+            LQUOTE | RQUOTE | LPAREN | RPAREN | COMMA | ENDPUNC | MIDPUNC | NFP | HYPH
+            | LPAREN_RAW | RPAREN_RAW | QUOTE => UniversalPos::PUNCT,
+        }
+    }
+}
+
+impl fmt::Display for PartOfSpeech {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.treebank())
+    }
+}
+
+impl FromStr for PartOfSpeech {
+    type Err = io::Error;
+
+    fn from_str(tag: &str) -> io::Result<PartOfSpeech> {
+        PartOfSpeech::from_treebank(tag)
+            .ok_or_else(|| invalid_data(&format!("unknown Penn Treebank tag {:?}", tag)))
+    }
+}
+
+// The tags a Tagger learns to choose between, e.g. PartOfSpeech, UniversalPos, or labels of
+// your own. A label set only needs ALL and name, e.g. for BIO entity labels:
+//   #[derive(Debug, Hash, Clone, Copy, Eq, PartialEq)]
+//   enum Entity { O, BPer, IPer }
+//   impl TagSet for Entity {
+//       const ALL: &'static [Entity] = &[Entity::O, Entity::BPer, Entity::IPer];
+//       fn index(&self) -> usize { *self as usize } // optional, but faster
+//       fn name(&self) -> &'static str { ["O", "B-PER", "I-PER"][self.index()] }
+//   }
+// Model files store tags by name, so a tag set can grow, or be reordered, without breaking them.
+// Hashed models are the exception: features are hashed with the tags' indexes, so new tags must
+// go at the end of ALL, and existing tags must keep their index
+// There can be at most 254 tags, with names of at most 255 bytes
+pub trait TagSet: Copy + Eq + Hash + Debug + Send + Sync + 'static {
+    const ALL: &'static [Self]; // ALL[tag.index()] == tag
+
+    // Scores and weights are indexed by this, so it's called a lot
+    fn index(&self) -> usize {
+        Self::ALL.iter().position(|tag| tag == self).unwrap()
+    }
+
+    // As in treebanks, and in model files. Unique in the tag set
+    fn name(&self) -> &'static str;
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().find(|tag| tag.name() == name).copied()
+    }
+
+    // The tag of a treebank word, read from column
+    fn from_conllu(word: &conllu::Word, column: TagColumn) -> Option<Self> {
+        match column {
+            TagColumn::Xpos => Self::from_name(&word.xpos),
+            TagColumn::Upos => Self::from_name(&word.upos),
+        }
+    }
+}
+
+impl TagSet for PartOfSpeech {
+    const ALL: &'static [PartOfSpeech] = &PartOfSpeech::ALL;
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+
+    fn name(&self) -> &'static str {
+        self.treebank()
+    }
+
+    fn from_name(name: &str) -> Option<PartOfSpeech> {
+        PartOfSpeech::from_treebank(name)
+    }
+
+    fn from_conllu(word: &conllu::Word, column: TagColumn) -> Option<PartOfSpeech> {
+        match column {
+            TagColumn::Xpos => PartOfSpeech::from_treebank(&word.xpos),
+            TagColumn::Upos => PartOfSpeech::from_universal(&word.upos, &word.form),
+        }
+    }
 }
 
-// 'doc is the lifetime of the doc
-// 'vocab is the lifetime of the vocab
 #[derive(Debug)]
-pub struct Token {
-    pub lemma:String,
-    pub part_of_speech: PartOfSpeech,
-}
-
-// Lives as long as the model is loaded
-// Don't want to allocate a whole new vec for each feature...
-enum Feature<'model>{
-    Bias,
-    Suffix {
-        word_index:i8,      // refers to the index of the word. Current word is 0. Next word is 1, Previous word is -1
-        value:&'model [u8], // the chars making up the first part
-    },
-    Prefix {
-        word_index:i8,
-        value:&'model [u8], // the chars making up the first part
-    },
-    Tag {
-        word_index:i8,
-        value:PartOfSpeech,
-    },
-    DoubleTag {
-        word_indexes:(i8, i8),
-        values: (PartOfSpeech, PartOfSpeech)
-    }
-}
-
-struct Perceptron<'model> {
-    weights:HashMap<Feature<'model>, HashMap<PartOfSpeech, f64>>,
-}
-
-impl<'model> Perceptron<'model> {
-    fn new() -> Perceptron<'model> {
+pub struct Token<T = PartOfSpeech> {
+    pub lemma: String,
+    pub part_of_speech: T,
+    pub score: f32,                  // see ScoredTag
+    pub alternatives: Vec<(T, f32)>, // see ScoredTag, empty from Tagger::tokenize
+}
+
+// A tag and how sure the tagger was of it: a softmax over the perceptron scores of the word's
+// candidate tags, given the tags chosen for the words before it. Words the dictionary fixes
+// score 1. Perceptron scores aren't probabilities, so check them with Calibration before
+// relying on them
+#[derive(Debug, Clone)]
+pub struct ScoredTag<T = PartOfSpeech> {
+    pub part_of_speech: T,
+    pub score: f32,
+    pub alternatives: Vec<(T, f32)>, // the k best tags, best first
+}
+
+// The hashing trick: rather than giving each feature its own row, features are hashed into
+// 2^bits rows, so memory and model size are fixed whatever the corpus
+// Features that land in the same row share weights, so too few bits costs accuracy
+#[derive(Debug, Clone, Copy, std::cmp::Eq, std::cmp::PartialEq)]
+pub struct Hashing {
+    pub bits: u8, // 1 to Hashing::MAX_BITS
+    pub seed: u64,
+}
+
+impl Hashing {
+    // 2^24 rows of 54 Penn Treebank tags are already 3.6 GB of weights
+    pub const MAX_BITS: u8 = 24;
+
+    pub fn rows(&self) -> usize {
+        1 << self.bits
+    }
+
+    fn row(&self, feature: &Feature) -> usize {
+        let mut hasher = Fnv::default();
+        hasher.write(&self.seed.to_le_bytes());
+        hasher.write(encode_feature(feature).as_bytes());
+        // FNV's low bits are weak, and only the low bits are used
+        let hash = hasher.finish();
+        ((hash ^ (hash >> 32)) as usize) & (self.rows() - 1)
+    }
+}
+
+// Averaged perceptron, as described here:
+// https://explosion.ai/blog/part-of-speech-pos-tagger-in-python
+// Features are interned to rows, and each row holds a weight per tag, indexed by TagSet::index.
+// While training, each weight also keeps the sum of its values over every instance seen so far.
+// Rather than adding to every sum on every instance, a weight's sum is caught up
+// (by its current value times the instances since it last changed) only when it changes
+struct Perceptron {
+    rows: HashMap<Feature, u32, BuildHasherDefault<Fnv>>, // empty when hashing
+    hashing: Option<Hashing>,
+    tags: usize,       // weights per row
+    weights: Vec<f32>, // row after row
+    // only used while training
+    totals: Vec<f64>,
+    timestamps: Vec<u64>,
+    instances: u64,
+}
+
+impl Perceptron {
+    fn new(hashing: Option<Hashing>, tags: usize) -> Perceptron {
+        if let Some(hashing) = hashing {
+            assert!(
+                (1..=Hashing::MAX_BITS).contains(&hashing.bits),
+                "hashing bits must be between 1 and {}",
+                Hashing::MAX_BITS
+            );
+        }
+        let rows = hashing.map_or(0, |hashing| hashing.rows());
         Perceptron {
-            weights: HashMap::new()
+            rows: HashMap::default(),
+            hashing,
+            tags,
+            weights: vec![0.0; rows * tags],
+            totals: Vec::new(),
+            timestamps: Vec::new(),
+            instances: 0,
+        }
+    }
+
+    // Only used while training
+    fn row(&mut self, feature: Feature) -> usize {
+        if let Some(hashing) = self.hashing {
+            if self.totals.is_empty() {
+                self.totals = vec![0.0; self.weights.len()];
+                self.timestamps = vec![0; self.weights.len()];
+            }
+            return hashing.row(&feature);
+        }
+        let next = (self.weights.len() / self.tags) as u32;
+        let row = *self.rows.entry(feature).or_insert(next);
+        if row == next {
+            self.weights.resize(self.weights.len() + self.tags, 0.0);
+            self.totals.resize(self.weights.len(), 0.0);
+            self.timestamps.resize(self.weights.len(), 0);
         }
+        row as usize
+    }
+
+    fn weights(&self, row: usize) -> &[f32] {
+        &self.weights[row * self.tags..(row + 1) * self.tags]
+    }
+
+    // Adds the score of each tag to scores
+    fn add_scores(&self, features: &[Feature], scores: &mut [f32]) {
+        for feature in features {
+            let row = match self.hashing {
+                Some(hashing) => hashing.row(feature),
+                None => match self.rows.get(feature) {
+                    Some(row) => *row as usize,
+                    None => continue,
+                },
+            };
+            for (score, weight) in scores.iter_mut().zip(self.weights(row)) {
+                *score += weight;
+            }
+        }
+    }
+
+    // The score of each tag, replacing what was in scores
+    fn scores(&self, features: &[Feature], scores: &mut Vec<f32>) {
+        scores.clear();
+        scores.resize(self.tags, 0.0);
+        self.add_scores(features, scores);
+    }
+
+    fn update(&mut self, truth: usize, guess: usize, features: &[Feature]) {
+        self.instances += 1;
+        if truth == guess {
+            return;
+        }
+        for feature in features {
+            let row = self.row(*feature);
+            self.update_weight(row, truth, 1.0);
+            self.update_weight(row, guess, -1.0);
+        }
+    }
+
+    fn update_weight(&mut self, row: usize, tag: usize, value: f32) {
+        let at = row * self.tags + tag;
+        let weight = &mut self.weights[at];
+        let timestamp = &mut self.timestamps[at];
+        self.totals[at] += (self.instances - *timestamp) as f64 * *weight as f64;
+        *timestamp = self.instances;
+        *weight += value;
+    }
+
+    // Replace each weight with its average over every instance, and drop rows left empty
+    // (unless hashing, where the rows are fixed). Ends training
+    fn average(&mut self) {
+        self.weights = self.averaged_weights();
+        self.totals = Vec::new();
+        self.timestamps = Vec::new();
+        self.drop_empty_rows();
+    }
+
+    fn averaged_weights(&self) -> Vec<f32> {
+        let instances = self.instances.max(1);
+        let mut weights = self.weights.clone();
+        let averaging = self.totals.iter().zip(self.timestamps.iter());
+        for (weight, (total, timestamp)) in weights.iter_mut().zip(averaging) {
+            let total = total + (instances - timestamp) as f64 * *weight as f64;
+            *weight = (total / instances as f64) as f32;
+        }
+        weights
+    }
+
+    fn drop_empty_rows(&mut self) {
+        if self.hashing.is_some() {
+            return;
+        }
+        let mut features: Vec<(Feature, u32)> = self.rows.drain().collect();
+        features.sort_unstable_by_key(|(_, row)| *row);
+        let weights = std::mem::take(&mut self.weights);
+        for (feature, row) in features {
+            let row = row as usize;
+            let weights = &weights[row * self.tags..(row + 1) * self.tags];
+            if weights.iter().any(|weight| *weight != 0.0) {
+                self.insert(feature, weights);
+            }
+        }
+    }
+
+    // A copy to train one shard of an epoch on, averaging only over that epoch
+    fn fork(&self) -> Perceptron {
+        Perceptron {
+            rows: self.rows.clone(),
+            hashing: self.hashing,
+            tags: self.tags,
+            weights: self.weights.clone(),
+            totals: vec![0.0; self.weights.len()],
+            timestamps: vec![0; self.weights.len()],
+            instances: 0,
+        }
+    }
+
+    // Iterative parameter mixing, as described here:
+    // https://aclanthology.org/N10-1069.pdf
+    // The weights become the sum of the forks' weights times their shares, which add up to 1.
+    // The forks' averaged weights are added to sums the same way, so sums over every epoch
+    // make the final average (see finish_mixing). Forks are mixed in order, so the result
+    // doesn't depend on which thread finished first
+    fn mix(&mut self, forks: &[(&Perceptron, f64)], sums: &mut Vec<f64>) {
+        self.weights.fill(0.0);
+        sums.resize(self.weights.len(), 0.0);
+        for (fork, share) in forks {
+            // rows in the order the fork added them, so new features get rows in a fixed order
+            let mut rows: Vec<(usize, usize)> = Vec::new();
+            if self.hashing.is_some() {
+                rows.extend((0..fork.weights.len() / fork.tags).map(|row| (row, row)));
+            } else {
+                let mut features: Vec<(&Feature, &u32)> = fork.rows.iter().collect();
+                features.sort_unstable_by_key(|(_, row)| **row);
+                for (feature, row) in features {
+                    let next = (self.weights.len() / self.tags) as u32;
+                    let to = *self.rows.entry(*feature).or_insert(next);
+                    if to == next {
+                        self.weights.resize(self.weights.len() + self.tags, 0.0);
+                        sums.resize(self.weights.len(), 0.0);
+                    }
+                    rows.push((*row as usize, to as usize));
+                }
+            }
+            let averaged = fork.averaged_weights();
+            for (from, to) in rows {
+                for tag in 0..self.tags {
+                    let (from, to) = (from * self.tags + tag, to * self.tags + tag);
+                    self.weights[to] += (share * fork.weights[from] as f64) as f32;
+                    sums[to] += share * averaged[from] as f64;
+                }
+            }
+        }
+    }
+
+    // The mean of the mixed averages of every epoch. Ends training
+    fn finish_mixing(&mut self, sums: &[f64], epochs: usize) {
+        for (weight, sum) in self.weights.iter_mut().zip(sums) {
+            *weight = (sum / epochs.max(1) as f64) as f32;
+        }
+        self.drop_empty_rows();
+    }
+
+    // The tags with a nonzero weight. The others always score 0, so they are never worth guessing
+    fn tags<T: TagSet>(&self) -> Vec<T> {
+        let mut seen = vec![false; self.tags];
+        for row in self.weights.chunks(self.tags) {
+            for (seen, weight) in seen.iter_mut().zip(row) {
+                *seen |= *weight != 0.0;
+            }
+        }
+        let tags: Vec<T> = T::ALL
+            .iter()
+            .filter(|tag| seen[tag.index()])
+            .copied()
+            .collect();
+        if tags.is_empty() {
+            T::ALL.to_vec()
+        } else {
+            tags
+        }
+    }
+
+    // Add a trained row, e.g. from a model file
+    fn insert(&mut self, feature: Feature, weights: &[f32]) {
+        let row = self.weights.len() / self.tags;
+        self.rows.insert(feature, row as u32);
+        self.weights.extend_from_slice(weights);
+    }
+}
+
+// The tags each frequent word was seen with in training
+// Words that nearly always had the same tag get just that tag, and skip the perceptron
+// Other frequent words get every tag they were seen with, and only those are scored
+#[derive(Debug, Clone)]
+pub struct TagDictionary<T: TagSet = PartOfSpeech> {
+    words: HashMap<u64, Vec<T>>, // word hash to tags, in TagSet::ALL order
+}
+
+impl<T: TagSet> Default for TagDictionary<T> {
+    fn default() -> TagDictionary<T> {
+        TagDictionary {
+            words: HashMap::new(),
+        }
+    }
+}
+
+impl<T: TagSet> TagDictionary<T> {
+    pub fn build(sentences: &[Vec<(Lexeme, T)>], options: &DictionaryOptions) -> TagDictionary<T> {
+        let mut counts: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (lexeme, tag) in sentences.iter().flatten() {
+            counts
+                .entry(features::word_hash(&lexeme.value))
+                .or_insert_with(|| vec![0; T::ALL.len()])[tag.index()] += 1;
+        }
+        let mut words = HashMap::new();
+        for (word, counts) in counts {
+            let total: usize = counts.iter().sum();
+            if total < options.min_count {
+                continue;
+            }
+            let (mut best, mut seen) = (0, Vec::new());
+            for (tag, count) in counts.iter().enumerate() {
+                if *count > counts[best] {
+                    best = tag;
+                }
+                if *count > 0 {
+                    seen.push(T::ALL[tag]);
+                }
+            }
+            if counts[best] as f64 / total as f64 >= options.min_ratio {
+                seen = vec![T::ALL[best]];
+            }
+            words.insert(word, seen);
+        }
+        TagDictionary { words }
+    }
+
+    // The tags a word can have, or None if any tag can be guessed for it
+    pub fn get(&self, value: &[u8]) -> Option<&[T]> {
+        self.candidates(features::word_hash(value))
+    }
+
+    // The tag of a word if it is unambiguous
+    pub fn tag(&self, value: &[u8]) -> Option<T> {
+        match self.get(value)? {
+            [tag] => Some(*tag),
+            _ => None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    fn candidates(&self, word_hash: u64) -> Option<&[T]> {
+        self.words.get(&word_hash).map(Vec::as_slice)
     }
 }
 
-pub fn tokenize(values:Vec<Lexeme>) -> Vec<Token> {
-    
+// Thresholds for the tag dictionary. The defaults are from Honnibal's tagger
+#[derive(Debug, Clone, Copy)]
+pub struct DictionaryOptions {
+    pub min_count: usize, // words seen fewer times than this aren't in the dictionary
+    pub min_ratio: f64,   // a word is unambiguous if this share of its tags is the same tag
 }
 
+impl Default for DictionaryOptions {
+    fn default() -> DictionaryOptions {
+        DictionaryOptions {
+            min_count: 20,
+            min_ratio: 0.97,
+        }
+    }
+}
+
+// Tags words left to right, see Decoder
+pub struct Tagger<T: TagSet = PartOfSpeech> {
+    perceptron: Perceptron,
+    tags: Vec<T>, // see Tagger::tags
+    templates: Vec<Template>,
+    split: SplitTemplates,
+    clusters: Clusters,
+    dictionary: TagDictionary<T>,
+    decoder: Decoder,
+    vocab_hash: Option<u64>,
+    vocab: Option<Arc<Vocab>>, // see Tagger::set_vocab
+}
+
+impl<T: TagSet> Tagger<T> {
+    // Train on sentences of lexemes with their gold tags
+    // Fails if a template couldn't be saved in the model, e.g. Suffix(0, 9)
+    pub fn train(sentences: &[Vec<(Lexeme, T)>], options: &TrainOptions) -> io::Result<Tagger<T>> {
+        Tagger::train_with_progress(sentences, options, |_| ())
+    }
+
+    // Same as train, calling progress after every epoch
+    pub fn train_with_progress(
+        sentences: &[Vec<(Lexeme, T)>],
+        options: &TrainOptions,
+        mut progress: impl FnMut(&Progress),
+    ) -> io::Result<Tagger<T>> {
+        let epochs = options.epochs;
+        check_templates(&options.templates)?;
+        check_decoder(options.decoder, &options.templates);
+        check_tags::<T>();
+        let split = SplitTemplates::new(&options.templates);
+        let mut perceptron = Perceptron::new(options.hashing, T::ALL.len());
+        let mut order: Vec<usize> = (0..sentences.len()).collect();
+        let mut rng = Rng(options.seed);
+        let templates = &options.templates;
+        let clusters = options.clusters.clone().unwrap_or_default();
+        let dictionary = match &options.dictionary {
+            Some(dictionary) => TagDictionary::build(sentences, dictionary),
+            None => TagDictionary::default(),
+        };
+        let training = Training {
+            options,
+            split: &split,
+            clusters: &clusters,
+            vocab: options.vocab.as_deref(),
+            dictionary: &dictionary,
+        };
+        // the sum over epochs of the mixed averages, see Perceptron::mix
+        let mut sums = Vec::new();
+        for epoch in 0..epochs {
+            rng.shuffle(&mut order);
+            let (tokens, correct) = if options.threads <= 1 {
+                training.epoch(&mut perceptron, order.iter().map(|i| &sentences[*i]))
+            } else {
+                // contiguous runs of the shuffled sentences, so shards change every epoch
+                let size = sentences.len().div_ceil(options.threads).max(1);
+                let shards: Vec<(Perceptron, usize, usize)> = thread::scope(|scope| {
+                    let threads: Vec<_> = order
+                        .chunks(size)
+                        .map(|shard| {
+                            let (training, perceptron) = (&training, &perceptron);
+                            scope.spawn(move || {
+                                let mut shard_perceptron = perceptron.fork();
+                                let sentences = shard.iter().map(|i| &sentences[*i]);
+                                let (tokens, correct) =
+                                    training.epoch(&mut shard_perceptron, sentences);
+                                (shard_perceptron, tokens, correct)
+                            })
+                        })
+                        .collect();
+                    threads
+                        .into_iter()
+                        .map(|thread| thread.join().unwrap())
+                        .collect()
+                });
+                // weighted by each shard's share of the tokens
+                let total: usize = sentences.iter().map(Vec::len).sum();
+                let shares: Vec<(&Perceptron, f64)> = order
+                    .chunks(size)
+                    .zip(shards.iter())
+                    .map(|(shard, (shard_perceptron, _, _))| {
+                        let tokens: usize = shard.iter().map(|i| sentences[*i].len()).sum();
+                        (shard_perceptron, tokens as f64 / total.max(1) as f64)
+                    })
+                    .collect();
+                perceptron.mix(&shares, &mut sums);
+                shards.iter().fold(
+                    (0, 0),
+                    |(tokens, correct), (_, shard_tokens, shard_correct)| {
+                        (tokens + shard_tokens, correct + shard_correct)
+                    },
+                )
+            };
+            progress(&Progress {
+                epoch: epoch + 1,
+                epochs,
+                tokens,
+                correct,
+            });
+        }
+        if options.threads <= 1 {
+            perceptron.average();
+        } else {
+            perceptron.finish_mixing(&sums, epochs);
+        }
+        Ok(Tagger {
+            tags: perceptron.tags(),
+            perceptron,
+            templates: templates.clone(),
+            split,
+            dictionary,
+            decoder: options.decoder,
+            vocab_hash: options
+                .clusters
+                .as_ref()
+                .map(|clusters| clusters.vocab_hash),
+            clusters,
+            vocab: options.vocab.clone(),
+        })
+    }
+
+    // Train on a CoNLL-U treebank, one lexeme per word, using the tags from column
+    pub fn train_conllu(
+        path: impl AsRef<Path>,
+        column: TagColumn,
+        options: &TrainOptions,
+        progress: impl FnMut(&Progress),
+    ) -> io::Result<Tagger<T>> {
+        let sentences = tagged_sentences(&conllu::read_file(path)?, column)?;
+        Tagger::train_with_progress(&sentences, options, progress)
+    }
+
+    pub fn hashing(&self) -> Option<Hashing> {
+        self.perceptron.hashing
+    }
+
+    // The tags the perceptron can guess, in TagSet::ALL order: those it has weights for, which
+    // leaves out tags the training data didn't have. The dictionary can give words other tags
+    pub fn tags(&self) -> &[T] {
+        &self.tags
+    }
+
+    pub fn templates(&self) -> &[Template] {
+        &self.templates
+    }
+
+    pub fn dictionary(&self) -> &TagDictionary<T> {
+        &self.dictionary
+    }
+
+    pub fn decoder(&self) -> Decoder {
+        self.decoder
+    }
+
+    // Models are loaded with the decoder they were saved with. Panics for Viterbi if a template
+    // looks further back than the previous tag
+    pub fn set_decoder(&mut self, decoder: Decoder) {
+        check_decoder(decoder, &self.templates);
+        self.decoder = decoder;
+    }
+
+    // The hash of the vocab the model's clusters came from, if it has them
+    // Pass it to Vocab::load_checked to load the matching vocab
+    pub fn vocab_hash(&self) -> Option<u64> {
+        self.vocab_hash
+    }
+
+    // Fails if the model was trained with a vocab, and it isn't this one
+    pub fn check_vocab(&self, vocab: &Vocab) -> io::Result<()> {
+        match self.vocab_hash {
+            Some(hash) if hash != vocab.hash() => Err(invalid_data(
+                "vocab does not match the one the model was built with",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // Read shapes and lexical attributes from the vocab's entries, rather than working them out
+    // for every word. Words not in the vocab still have theirs worked out
+    // Models don't store the vocab, so it has to be set again after loading
+    pub fn set_vocab(&mut self, vocab: Arc<Vocab>) -> io::Result<()> {
+        self.check_vocab(&vocab)?;
+        self.vocab = Some(vocab);
+        Ok(())
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut file = io::BufWriter::new(File::create(path)?);
+        self.write_to(&mut file)?;
+        file.flush()
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&self.vocab_hash.unwrap_or(0).to_le_bytes())?;
+        writer.write_all(&(T::ALL.len() as u32).to_le_bytes())?;
+        for tag in T::ALL.iter() {
+            write_string(writer, tag.name())?;
+        }
+        writer.write_all(&(self.templates.len() as u32).to_le_bytes())?;
+        for template in self.templates.iter() {
+            write_string(writer, &template.name())?;
+        }
+        let mut clusters: Vec<(&u64, &u64)> = self.clusters.words.iter().collect();
+        clusters.sort_unstable();
+        writer.write_all(&(clusters.len() as u64).to_le_bytes())?;
+        for (word, cluster) in clusters {
+            writer.write_all(&word.to_le_bytes())?;
+            writer.write_all(&cluster.to_le_bytes())?;
+        }
+        let mut dictionary: Vec<(&u64, &Vec<T>)> = self.dictionary.words.iter().collect();
+        dictionary.sort_unstable_by_key(|(word, _)| **word);
+        writer.write_all(&(dictionary.len() as u64).to_le_bytes())?;
+        for (word, tags) in dictionary {
+            writer.write_all(&word.to_le_bytes())?;
+            writer.write_all(&[tags.len() as u8])?;
+            for tag in tags {
+                writer.write_all(&[tag.index() as u8])?;
+            }
+        }
+        let (decoder, width) = match self.decoder {
+            Decoder::Greedy => (0, 0),
+            Decoder::Beam(width) => (1, width as u32),
+            Decoder::Viterbi => (2, 0),
+        };
+        writer.write_all(&[decoder])?;
+        writer.write_all(&width.to_le_bytes())?;
+
+        let perceptron = &self.perceptron;
+        if let Some(hashing) = perceptron.hashing {
+            writer.write_all(&[hashing.bits])?;
+            writer.write_all(&hashing.seed.to_le_bytes())?;
+            for weight in perceptron.weights.iter() {
+                writer.write_all(&weight.to_le_bytes())?;
+            }
+            return Ok(());
+        }
+        writer.write_all(&[0])?;
+        writer.write_all(&0u64.to_le_bytes())?;
+
+        // sorted, so the same model always makes the same file
+        let mut features: Vec<(EncodedFeature, &[f32])> = perceptron
+            .rows
+            .iter()
+            .map(|(feature, row)| (encode_feature(feature), perceptron.weights(*row as usize)))
+            .collect();
+        features.sort_unstable_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+        writer.write_all(&(features.len() as u64).to_le_bytes())?;
+        for (feature, weights) in features {
+            writer.write_all(feature.as_bytes())?;
+            let nonzero = weights.iter().filter(|weight| **weight != 0.0).count();
+            writer.write_all(&[nonzero as u8])?;
+            for (tag, weight) in weights.iter().enumerate() {
+                if *weight != 0.0 {
+                    writer.write_all(&[tag as u8])?;
+                    writer.write_all(&weight.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Tagger<T>> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        Tagger::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Tagger<T>> {
+        let mut reader = ModelReader { bytes, at: 0 };
+        if reader.take(8).ok() != Some(&MAGIC[..]) {
+            return Err(invalid_data("not a tagger model"));
+        }
+        let version = reader.u32()?;
+        if version == 0 || version > VERSION {
+            return Err(invalid_data(&format!(
+                "unsupported tagger model version {}",
+                version
+            )));
+        }
+        reader.u32()?;
+        let vocab_hash = Some(reader.u64()?).filter(|hash| *hash != 0);
+
+        // tags are stored by name, so interned models survive the tag set being reordered
+        let mut tags = Vec::new();
+        for _ in 0..reader.u32()? {
+            let name = reader.string()?;
+            tags.push(T::from_name(name).ok_or_else(|| {
+                invalid_data(&format!("tagger model has an unknown tag {:?}", name))
+            })?);
+        }
+        let mut templates = Vec::new();
+        for _ in 0..reader.u32()? {
+            let name = reader.string()?;
+            templates.push(Template::from_name(name).ok_or_else(|| {
+                invalid_data(&format!("tagger model has an unknown template {:?}", name))
+            })?);
+        }
+        let mut clusters = Clusters {
+            words: HashMap::new(),
+            vocab_hash: vocab_hash.unwrap_or(0),
+        };
+        let count = if version < 4 { 0 } else { reader.u64()? };
+        for _ in 0..count {
+            let word = reader.u64()?;
+            clusters.words.insert(word, reader.u64()?);
+        }
+        let mut dictionary = TagDictionary::default();
+        let count = if version < 5 { 0 } else { reader.u64()? };
+        for _ in 0..count {
+            let word = reader.u64()?;
+            let mut candidates = Vec::new();
+            for _ in 0..reader.u8()? {
+                candidates.push(reader.tag(&tags)?.ok_or_else(|| invalid_data("bad tag"))?);
+            }
+            // in TagSet::ALL order, so ties are broken the same way
+            candidates.sort_unstable_by_key(|tag| tag.index());
+            if candidates.is_empty() {
+                return Err(invalid_data("dictionary word has no tags"));
+            }
+            dictionary.words.insert(word, candidates);
+        }
+        let decoder = if version < 6 {
+            Decoder::Greedy
+        } else {
+            match (reader.u8()?, reader.u32()?) {
+                (0, _) => Decoder::Greedy,
+                (1, width) if width > 0 => Decoder::Beam(width as usize),
+                (2, _) if templates.iter().all(|template| template.history() <= 1) => {
+                    Decoder::Viterbi
+                }
+                _ => return Err(invalid_data("bad decoder")),
+            }
+        };
+
+        let hashing = match version {
+            1 | 2 => None,
+            _ => {
+                let (bits, seed) = (reader.u8()?, reader.u64()?);
+                if bits > Hashing::MAX_BITS {
+                    return Err(invalid_data("bad hashing bits"));
+                }
+                Some(Hashing { bits, seed }).filter(|_| bits != 0)
+            }
+        };
+        // hashed rows depend on the tag indexes the model was trained with, and can't be remapped
+        if hashing.is_some() && tags.iter().enumerate().any(|(i, tag)| tag.index() != i) {
+            return Err(invalid_data(
+                "hashed tagger model's tags are in a different order to the tag set",
+            ));
+        }
+        // checked before the weights are allocated, so a corrupt file can't ask for gigabytes
+        if let Some(hashing) = hashing {
+            let len = hashing
+                .rows()
+                .checked_mul(tags.len())
+                .and_then(|weights| weights.checked_mul(4));
+            if len.is_none_or(|len| len > reader.remaining()) {
+                return Err(invalid_data("tagger model is truncated"));
+            }
+        }
+        // tags the file doesn't have keep zero weights
+        let mut perceptron = Perceptron::new(hashing, T::ALL.len());
+        for row in 0..hashing.map_or(0, |hashing| hashing.rows()) {
+            for tag in tags.iter() {
+                perceptron.weights[row * T::ALL.len() + tag.index()] = reader.f32()?;
+            }
+        }
+        let features = if hashing.is_some() { 0 } else { reader.u64()? };
+        let mut weights = vec![0.0; T::ALL.len()];
+        for _ in 0..features {
+            let feature = reader.feature(&tags)?;
+            weights.fill(0.0);
+            for _ in 0..reader.u8()? {
+                let tag = reader.tag(&tags)?.ok_or_else(|| invalid_data("bad tag"))?;
+                weights[tag.index()] = match version {
+                    1 => reader.f64()? as f32,
+                    _ => reader.f32()?,
+                };
+            }
+            perceptron.insert(feature, &weights);
+        }
+        if reader.at != bytes.len() {
+            return Err(invalid_data("tagger model has trailing data"));
+        }
+        Ok(Tagger {
+            tags: perceptron.tags(),
+            perceptron,
+            split: SplitTemplates::new(&templates),
+            templates,
+            clusters,
+            dictionary,
+            decoder,
+            vocab_hash,
+            vocab: None,
+        })
+    }
+
+    pub fn tag(&self, lexemes: &[Lexeme]) -> Vec<T> {
+        let words = self.words(lexemes);
+        self.decode(&mut self.scorer(&words))
+    }
+
+    // Tags with their scores, and the k best tags of each word
+    pub fn tag_scored(&self, lexemes: &[Lexeme], k: usize) -> Vec<ScoredTag<T>> {
+        let words = self.words(lexemes);
+        let mut scorer = self.scorer(&words);
+        let tags = self.decode(&mut scorer);
+        let mut scored = Vec::with_capacity(tags.len());
+        let mut scores = Vec::new();
+        for (i, part_of_speech) in tags.iter().enumerate() {
+            let mut probabilities = match scorer.candidates(i) {
+                [tag] => vec![(*tag, 1.0)],
+                _ => {
+                    let previous = i.checked_sub(1).map(|j| tags[j]);
+                    let previous2 = i.checked_sub(2).map(|j| tags[j]);
+                    scorer.scores(i, previous, previous2, &mut scores);
+                    softmax(&scores, scorer.candidates(i))
+                }
+            };
+            let score = probabilities
+                .iter()
+                .find(|(tag, _)| tag == part_of_speech)
+                .map_or(0.0, |(_, score)| *score);
+            // stable, so ties keep TagSet::ALL order
+            probabilities.sort_by(|a, b| b.1.total_cmp(&a.1));
+            probabilities.truncate(k);
+            scored.push(ScoredTag {
+                part_of_speech: *part_of_speech,
+                score,
+                alternatives: probabilities,
+            });
+        }
+        scored
+    }
+
+    pub fn tokenize(&self, values: Vec<Lexeme>) -> Vec<Token<T>> {
+        self.tokenize_with_alternatives(values, 0)
+    }
+
+    // Same as tokenize, keeping the k best tags of each token
+    pub fn tokenize_with_alternatives(&self, values: Vec<Lexeme>, k: usize) -> Vec<Token<T>> {
+        let tags = self.tag_scored(&values, k);
+        values
+            .into_iter()
+            .zip(tags)
+            .map(|(lexeme, tag)| Token {
+                lemma: String::from_utf8_lossy(&lexeme.value).into_owned(),
+                part_of_speech: tag.part_of_speech,
+                score: tag.score,
+                alternatives: tag.alternatives,
+            })
+            .collect()
+    }
+
+    fn words<'a>(&self, lexemes: &'a [Lexeme]) -> Vec<features::Word<'a>> {
+        features::words(
+            lexemes.iter(),
+            &self.templates,
+            &self.clusters,
+            self.vocab.as_deref(),
+        )
+    }
+
+    fn scorer<'a>(&'a self, words: &'a [features::Word<'a>]) -> SentenceScorer<'a, T> {
+        SentenceScorer::new(
+            &self.perceptron,
+            &self.tags,
+            &self.split,
+            words,
+            &self.dictionary,
+        )
+    }
+
+    fn decode(&self, scorer: &mut SentenceScorer<T>) -> Vec<T> {
+        match self.decoder {
+            Decoder::Greedy => decoder::greedy(scorer),
+            Decoder::Beam(width) => decoder::beam(scorer, width, None),
+            Decoder::Viterbi => decoder::viterbi(scorer),
+        }
+    }
+}
+
+// The probability of each candidate
+fn softmax<T: TagSet>(scores: &[f32], candidates: &[T]) -> Vec<(T, f32)> {
+    let max = candidates
+        .iter()
+        .map(|tag| scores[tag.index()])
+        .fold(f32::NEG_INFINITY, f32::max);
+    let exps: Vec<f32> = candidates
+        .iter()
+        .map(|tag| (scores[tag.index()] - max).exp())
+        .collect();
+    let sum: f32 = exps.iter().sum();
+    candidates
+        .iter()
+        .zip(exps)
+        .map(|(tag, exp)| (*tag, exp / sum))
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct TrainOptions {
+    pub epochs: usize,
+    pub seed: u64, // sentences are shuffled before each epoch, starting from this
+    pub hashing: Option<Hashing>,
+    pub templates: Vec<Template>, // Template::standard() by default, see Template::groups
+    pub clusters: Option<Clusters>, // needed by Template::Cluster, which are off by default
+    pub vocab: Option<Arc<Vocab>>, // kept by the tagger, see Tagger::set_vocab
+    pub dictionary: Option<DictionaryOptions>, // None to score every word with the perceptron
+    pub decoder: Decoder,         // used for training, and kept for tagging
+    pub update: Update,           // for Decoder::Beam
+    // More than 1 trains shards of each epoch on this many threads, then mixes their weights
+    // (see Perceptron::mix). The model depends on the seed and threads, but not on timing
+    pub threads: usize,
+}
+
+impl Default for TrainOptions {
+    fn default() -> TrainOptions {
+        TrainOptions {
+            epochs: 5,
+            seed: 0,
+            hashing: None,
+            templates: Template::standard(),
+            clusters: None,
+            vocab: None,
+            dictionary: Some(DictionaryOptions::default()),
+            decoder: Decoder::Greedy,
+            update: Update::MaxViolation,
+            threads: 1,
+        }
+    }
+}
+
+// What every thread needs to train, besides a perceptron
+struct Training<'a, T: TagSet> {
+    options: &'a TrainOptions,
+    split: &'a SplitTemplates,
+    clusters: &'a Clusters,
+    vocab: Option<&'a Vocab>,
+    dictionary: &'a TagDictionary<T>,
+}
+
+impl<T: TagSet> Training<'_, T> {
+    // Learn from each sentence in turn. Returns the tokens seen, and how many were guessed right
+    fn epoch<'s>(
+        &self,
+        perceptron: &mut Perceptron,
+        sentences: impl Iterator<Item = &'s Vec<(Lexeme, T)>>,
+    ) -> (usize, usize) {
+        let (options, dictionary) = (self.options, self.dictionary);
+        let templates = &options.templates;
+        let (mut buffer, mut scores) = (Vec::new(), Vec::new());
+        let (mut tokens, mut correct) = (0, 0);
+        for sentence in sentences {
+            let lexemes = sentence.iter().map(|(lexeme, _)| lexeme);
+            let words = features::words(lexemes, templates, self.clusters, self.vocab);
+            if options.decoder != Decoder::Greedy {
+                // words the dictionary fixes are taken as right
+                let truth: Vec<T> = sentence
+                    .iter()
+                    .zip(words.iter())
+                    .map(
+                        |((_, truth), word)| match dictionary.candidates(word.hash) {
+                            Some([tag]) => *tag,
+                            _ => *truth,
+                        },
+                    )
+                    .collect();
+                let mut scorer =
+                    SentenceScorer::new(perceptron, T::ALL, self.split, &words, dictionary);
+                let guess = match options.decoder {
+                    Decoder::Beam(width) => {
+                        decoder::beam(&mut scorer, width, Some((&truth, options.update)))
+                    }
+                    _ => decoder::viterbi(&mut scorer),
+                };
+                let buffer = &mut buffer;
+                update_tagging(
+                    perceptron, templates, &words, dictionary, &truth, &guess, buffer,
+                );
+                tokens += guess.len();
+                correct += guess
+                    .iter()
+                    .zip(sentence)
+                    .filter(|(guess, (_, truth))| *guess == truth)
+                    .count();
+                continue;
+            }
+            let (mut previous, mut previous2) = (None, None);
+            for (i, (_, truth)) in sentence.iter().enumerate() {
+                let candidates = dictionary.candidates(words[i].hash).unwrap_or(&[]);
+                let guess = match candidates {
+                    // never scored when tagging, so not worth learning
+                    [tag] => *tag,
+                    _ => {
+                        let buffer = &mut buffer;
+                        features::features(buffer, templates, &words, i, previous, previous2);
+                        perceptron.scores(buffer, &mut scores);
+                        let guess = decoder::best(&scores, candidates);
+                        perceptron.update(truth.index(), guess.index(), buffer);
+                        guess
+                    }
+                };
+                tokens += 1;
+                correct += (guess == *truth) as usize;
+                // later words see the guess, as they will when tagging
+                previous2 = previous;
+                previous = Some(guess);
+            }
+        }
+        (tokens, correct)
+    }
+}
+
+// Learn from the best tagging of a sentence, or of its first words (see decoder::Update)
+// Words the dictionary fixes are never scored, so they aren't learned from
+fn update_tagging<T: TagSet>(
+    perceptron: &mut Perceptron,
+    templates: &[Template],
+    words: &[features::Word],
+    dictionary: &TagDictionary<T>,
+    truth: &[T],
+    guess: &[T],
+    buffer: &mut Vec<Feature>,
+) {
+    perceptron.instances += guess.len() as u64;
+    let truth = &truth[..guess.len()];
+    if truth == guess {
+        return;
+    }
+    for (tags, value) in [(truth, 1.0), (guess, -1.0)] {
+        for i in 0..tags.len() {
+            if let Some([_]) = dictionary.candidates(words[i].hash) {
+                continue;
+            }
+            let previous = i.checked_sub(1).map(|j| tags[j]);
+            let previous2 = i.checked_sub(2).map(|j| tags[j]);
+            features::features(buffer, templates, words, i, previous, previous2);
+            for feature in buffer.iter() {
+                let row = perceptron.row(*feature);
+                perceptron.update_weight(row, tags[i].index(), value);
+            }
+        }
+    }
+}
+
+// See TagSet
+fn check_tags<T: TagSet>() {
+    assert!(T::ALL.len() < NO_TAG as usize, "too many tags");
+    for (i, tag) in T::ALL.iter().enumerate() {
+        assert_eq!(
+            tag.index(),
+            i,
+            "{:?} isn't at its index in TagSet::ALL",
+            tag
+        );
+        assert!(
+            tag.name().len() <= u8::MAX as usize,
+            "{:?} has too long a name",
+            tag
+        );
+    }
+}
+
+// Models store templates by name, so any Template::from_name doesn't give back couldn't be loaded
+fn check_templates(templates: &[Template]) -> io::Result<()> {
+    match templates
+        .iter()
+        .find(|template| Template::from_name(&template.name()) != Some(**template))
+    {
+        Some(template) => Err(invalid_data(&format!(
+            "template {:?} is out of range",
+            template
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn check_decoder(decoder: Decoder, templates: &[Template]) {
+    if decoder == Decoder::Viterbi {
+        assert!(
+            templates.iter().all(|template| template.history() <= 1),
+            "the viterbi decoder only works with templates that look at the previous tag alone"
+        );
+    }
+}
+
+// Templates split by whether they look at the tags before a word
+struct SplitTemplates {
+    words: Vec<Template>,
+    tags: Vec<Template>,
+}
+
+impl SplitTemplates {
+    fn new(templates: &[Template]) -> SplitTemplates {
+        let (tags, words) = templates
+            .iter()
+            .partition(|template| template.history() > 0);
+        SplitTemplates { words, tags }
+    }
+}
+
+// Scores for the decoders. Features that don't depend on the tags before a word are only
+// scored once per word, however many taggings are searched
+struct SentenceScorer<'a, T: TagSet> {
+    perceptron: &'a Perceptron,
+    tags: &'a [T], // the candidates of words the dictionary doesn't have
+    templates: &'a SplitTemplates,
+    words: &'a [features::Word<'a>],
+    dictionary: &'a TagDictionary<T>,
+    word_scores: Vec<f32>, // word after word, see scored
+    scored: Vec<bool>,
+    buffer: Vec<Feature>,
+}
+
+impl<'a, T: TagSet> SentenceScorer<'a, T> {
+    fn new(
+        perceptron: &'a Perceptron,
+        tags: &'a [T],
+        templates: &'a SplitTemplates,
+        words: &'a [features::Word<'a>],
+        dictionary: &'a TagDictionary<T>,
+    ) -> SentenceScorer<'a, T> {
+        SentenceScorer {
+            perceptron,
+            tags,
+            templates,
+            words,
+            dictionary,
+            word_scores: vec![0.0; words.len() * perceptron.tags],
+            scored: vec![false; words.len()],
+            buffer: Vec::new(),
+        }
+    }
+}
+
+impl<T: TagSet> Scorer<T> for SentenceScorer<'_, T> {
+    fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    fn candidates(&self, i: usize) -> &[T] {
+        self.dictionary
+            .candidates(self.words[i].hash)
+            .unwrap_or(self.tags)
+    }
+
+    fn scores(
+        &mut self,
+        i: usize,
+        previous: Option<T>,
+        previous2: Option<T>,
+        scores: &mut Vec<f32>,
+    ) {
+        let buffer = &mut self.buffer;
+        let tags = self.perceptron.tags;
+        let word_scores = &mut self.word_scores[i * tags..(i + 1) * tags];
+        if !self.scored[i] {
+            features::features::<T>(buffer, &self.templates.words, self.words, i, None, None);
+            self.perceptron.add_scores(buffer, word_scores);
+            self.scored[i] = true;
+        }
+        scores.clear();
+        scores.extend_from_slice(word_scores);
+        features::features(
+            buffer,
+            &self.templates.tags,
+            self.words,
+            i,
+            previous,
+            previous2,
+        );
+        self.perceptron.add_scores(buffer, scores);
+    }
+}
+
+// Which CoNLL-U column the gold tags are read from
+#[derive(Debug, Clone, Copy, std::cmp::Eq, std::cmp::PartialEq)]
+pub enum TagColumn {
+    Xpos, // Penn Treebank tags, as in the English treebanks
+    Upos, // Universal POS tags
+}
+
+// How an epoch of training went. Accuracy is of the guesses made while training
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub epoch: usize, // counting from 1
+    pub epochs: usize,
+    pub tokens: usize,
+    pub correct: usize,
+}
+
+impl Progress {
+    pub fn accuracy(&self) -> f64 {
+        self.correct as f64 / self.tokens.max(1) as f64
+    }
+}
+
+// The tag of each word of a treebank sentence (see TagSet::from_conllu)
+// Fails on tags that aren't in the tag set, naming the tag and the sentence it is in
+pub fn gold_tags<T: TagSet>(sentence: &conllu::Sentence, column: TagColumn) -> io::Result<Vec<T>> {
+    sentence
+        .words
+        .iter()
+        .map(|word| {
+            let tag = match column {
+                TagColumn::Xpos => &word.xpos,
+                TagColumn::Upos => &word.upos,
+            };
+            T::from_conllu(word, column).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "unknown tag {:?} for {:?} in {:?}",
+                        tag, word.form, sentence.text
+                    ),
+                )
+            })
+        })
+        .collect()
+}
+
+// Pair each word of a treebank with its tag, keeping the treebank's tokenization
+pub fn tagged_sentences<T: TagSet>(
+    sentences: &[conllu::Sentence],
+    column: TagColumn,
+) -> io::Result<Vec<Vec<(Lexeme, T)>>> {
+    sentences
+        .iter()
+        .map(|sentence| {
+            let tags = gold_tags(sentence, column)?;
+            Ok(sentence.lexemes().into_iter().zip(tags).collect())
+        })
+        .collect()
+}
+
+// Lexemize the text of each treebank sentence, and project the gold tags onto the lexemes
+// (see Alignment::project), so the tagger is trained on what it will see when tagging
+// Lexemes that don't overlap any gold token are left out
+pub fn aligned_sentences<T: TagSet>(
+    ruleset: &RuleSet,
+    sentences: &[conllu::Sentence],
+    column: TagColumn,
+) -> io::Result<Vec<Vec<(Lexeme, T)>>> {
+    sentences
+        .iter()
+        .map(|sentence| {
+            let tags = gold_tags(sentence, column)?;
+            let lexemes = ruleset.lexemize(sentence.text.clone());
+            let projected = Alignment::conllu(&lexemes, sentence).project(&tags);
+            Ok(lexemes
+                .into_iter()
+                .zip(projected)
+                .filter_map(|(lexeme, tag)| Some((lexeme, tag?)))
+                .collect())
+        })
+        .collect()
+}
+
+// The lexemes are treated as a single sentence
+pub fn tokenize<T: TagSet>(tagger: &Tagger<T>, values: Vec<Lexeme>) -> Vec<Token<T>> {
+    tagger.tokenize(values)
+}
+
+// On disk format for tagger models, all integers little endian:
+//   magic "YGTAGGR\0"
+//   version u32, reserved u32
+//   vocab hash u64 (0 if the model has no clusters)
+//   tag count u32, then each tag's TagSet::name (strings are a u8 length and the bytes)
+//   template count u32, then each template's name (see Template::name)
+//   cluster count u64, then per word, sorted: word hash u64 (see features::word_hash), cluster u64
+//   dictionary word count u64, then per word, sorted: word hash u64, tag count u8, tags u8
+//   decoder u8 (0 greedy, 1 beam, 2 viterbi), beam width u32 (0 unless beam)
+//   hashing bits u8 (0 if not hashing), hashing seed u64
+//   if hashing, 2^bits rows of a weight f32 per tag (in the order of the tags above)
+//   otherwise feature count u64, then per feature, sorted by its encoding:
+//     the feature (see encode_feature)
+//     weight count u8, then per nonzero weight: tag u8 (an index into the tags above), weight f32
+// Version 1 files have f64 weights, neither version 1 or 2 have the hashing fields,
+// versions before 4 have no clusters, versions before 5 no dictionary, and versions before 6
+// no decoder (they load with the greedy one)
+const MAGIC: &[u8; 8] = b"YGTAGGR\0";
+const VERSION: u32 = 6;
+const NO_TAG: u8 = u8::MAX;
+const CASES: [Case; 5] = [
+    Case::Lower,
+    Case::Title,
+    Case::Upper,
+    Case::Mixed,
+    Case::Uncased,
+];
+
+// kind u8, then
+//   Suffix, Prefix: word index i8, affix length u8, affix bytes
+//   Tag: word index i8, tag u8 (its TagSet::index, NO_TAG for None)
+//   DoubleTag: word indexes i8 i8, tags u8 u8
+//   Cluster: word index i8, cluster u64
+//   Word, Shape: word index i8, hash u64
+//   Case: word index i8, case u8 (see CASES)
+//   HasHyphen, HasDigit: word index i8, 0 or 1
+//   TagWord: word index i8, tag u8, word hash u64
+//   Lexical: word index i8, flags u8 (see Attributes::flags)
+// This is also what gets hashed in hashing mode, so it must not depend on the platform
+fn encode_feature(feature: &Feature) -> EncodedFeature {
+    let tag = |tag: Option<u8>| tag.unwrap_or(NO_TAG);
+    let mut encoded = EncodedFeature {
+        len: 0,
+        bytes: [0; 16],
+    };
+    match feature {
+        Feature::Bias => encoded.push(&[0]),
+        Feature::Suffix { word_index, value } => {
+            encoded.push(&[1, *word_index as u8, value.len]);
+            encoded.push(&value.bytes[..value.len as usize]);
+        }
+        Feature::Prefix { word_index, value } => {
+            encoded.push(&[2, *word_index as u8, value.len]);
+            encoded.push(&value.bytes[..value.len as usize]);
+        }
+        Feature::Tag { word_index, value } => encoded.push(&[3, *word_index as u8, tag(*value)]),
+        Feature::DoubleTag {
+            word_indexes,
+            values,
+        } => encoded.push(&[
+            4,
+            word_indexes.0 as u8,
+            word_indexes.1 as u8,
+            tag(values.0),
+            tag(values.1),
+        ]),
+        Feature::Cluster { word_index, value } => {
+            encoded.push(&[5, *word_index as u8]);
+            encoded.push(&value.to_le_bytes());
+        }
+        Feature::Word { word_index, value } => {
+            encoded.push(&[6, *word_index as u8]);
+            encoded.push(&value.to_le_bytes());
+        }
+        Feature::Shape { word_index, value } => {
+            encoded.push(&[7, *word_index as u8]);
+            encoded.push(&value.to_le_bytes());
+        }
+        Feature::Case { word_index, value } => {
+            let case = CASES.iter().position(|case| case == value).unwrap();
+            encoded.push(&[8, *word_index as u8, case as u8]);
+        }
+        Feature::HasHyphen { word_index, value } => {
+            encoded.push(&[9, *word_index as u8, *value as u8])
+        }
+        Feature::HasDigit { word_index, value } => {
+            encoded.push(&[10, *word_index as u8, *value as u8])
+        }
+        Feature::TagWord {
+            word_index,
+            tag: previous,
+            value,
+        } => {
+            encoded.push(&[11, *word_index as u8, tag(*previous)]);
+            encoded.push(&value.to_le_bytes());
+        }
+        Feature::Lexical { word_index, value } => encoded.push(&[12, *word_index as u8, *value]),
+    }
+    encoded
+}
+
+struct EncodedFeature {
+    len: usize,
+    bytes: [u8; 16],
+}
+
+impl EncodedFeature {
+    fn push(&mut self, bytes: &[u8]) {
+        self.bytes[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+fn write_string(writer: &mut impl Write, string: &str) -> io::Result<()> {
+    writer.write_all(&[string.len() as u8])?;
+    writer.write_all(string.as_bytes())
+}
+
+struct ModelReader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> ModelReader<'a> {
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.at
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() - self.at < len {
+            return Err(invalid_data("tagger model is truncated"));
+        }
+        self.at += len;
+        Ok(&self.bytes[self.at - len..self.at])
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> io::Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> io::Result<f64> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> io::Result<&'a str> {
+        let len = self.u8()? as usize;
+        std::str::from_utf8(self.take(len)?).map_err(|_| invalid_data("bad string"))
+    }
+
+    fn tag<T: TagSet>(&mut self, tags: &[T]) -> io::Result<Option<T>> {
+        match self.u8()? {
+            NO_TAG => Ok(None),
+            tag => tags
+                .get(tag as usize)
+                .map(|tag| Some(*tag))
+                .ok_or_else(|| invalid_data("bad tag")),
+        }
+    }
+
+    // The tag of a feature, as an index into TagSet::ALL
+    fn tag_index<T: TagSet>(&mut self, tags: &[T]) -> io::Result<Option<u8>> {
+        Ok(self.tag(tags)?.map(|tag| tag.index() as u8))
+    }
+
+    fn feature<T: TagSet>(&mut self, tags: &[T]) -> io::Result<Feature> {
+        let kind = self.u8()?;
+        if kind == 0 {
+            return Ok(Feature::Bias);
+        }
+        let word_index = self.u8()? as i8;
+        Ok(match kind {
+            1 | 2 => {
+                let len = self.u8()? as usize;
+                if len > 7 {
+                    return Err(invalid_data("bad affix"));
+                }
+                let value = Affix::new(self.take(len)?);
+                if kind == 1 {
+                    Feature::Suffix { word_index, value }
+                } else {
+                    Feature::Prefix { word_index, value }
+                }
+            }
+            3 => Feature::Tag {
+                word_index,
+                value: self.tag_index(tags)?,
+            },
+            4 => Feature::DoubleTag {
+                word_indexes: (word_index, self.u8()? as i8),
+                values: (self.tag_index(tags)?, self.tag_index(tags)?),
+            },
+            5 => Feature::Cluster {
+                word_index,
+                value: self.u64()?,
+            },
+            6 => Feature::Word {
+                word_index,
+                value: self.u64()?,
+            },
+            7 => Feature::Shape {
+                word_index,
+                value: self.u64()?,
+            },
+            8 => Feature::Case {
+                word_index,
+                value: *CASES
+                    .get(self.u8()? as usize)
+                    .ok_or_else(|| invalid_data("bad case"))?,
+            },
+            9 => Feature::HasHyphen {
+                word_index,
+                value: self.u8()? != 0,
+            },
+            10 => Feature::HasDigit {
+                word_index,
+                value: self.u8()? != 0,
+            },
+            11 => Feature::TagWord {
+                word_index,
+                tag: self.tag_index(tags)?,
+                value: self.u64()?,
+            },
+            12 => Feature::Lexical {
+                word_index,
+                value: self.u8()?,
+            },
+            _ => return Err(invalid_data("bad feature")),
+        })
+    }
+}
+
+// splitmix64, so shuffling is the same everywhere for a given seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn shuffle<T>(&mut self, values: &mut [T]) {
+        for i in (1..values.len()).rev() {
+            values.swap(i, (self.next() % (i as u64 + 1)) as usize);
+        }
+    }
+}
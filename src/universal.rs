@@ -0,0 +1,113 @@
+use super::conllu;
+use super::tokenizer::PartOfSpeech;
+use super::tokenizer::TagColumn;
+use super::tokenizer::TagSet;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+// Universal POS tags, the coarse tags every Universal Dependencies treebank has. Definitions from here:
+// https://universaldependencies.org/u/pos/
+#[derive(Debug, std::hash::Hash, Clone, Copy, std::cmp::Eq, std::cmp::PartialEq)]
+pub enum UniversalPos {
+    ADJ,   // adjective
+    ADP,   // adposition
+    ADV,   // adverb
+    AUX,   // auxiliary
+    CCONJ, // coordinating conjunction
+    DET,   // determiner
+    INTJ,  // interjection
+    NOUN,  // noun
+    NUM,   // numeral
+    PART,  // particle
+    PRON,  // pronoun
+    PROPN, // proper noun
+    PUNCT, // punctuation
+    SCONJ, // subordinating conjunction
+    SYM,   // symbol
+    VERB,  // verb
+    X,     // other
+}
+
+impl UniversalPos {
+    pub const COUNT: usize = 17;
+
+    // In declaration order, so UniversalPos::ALL[tag as usize] == tag
+    pub const ALL: [UniversalPos; UniversalPos::COUNT] = [
+        UniversalPos::ADJ,
+        UniversalPos::ADP,
+        UniversalPos::ADV,
+        UniversalPos::AUX,
+        UniversalPos::CCONJ,
+        UniversalPos::DET,
+        UniversalPos::INTJ,
+        UniversalPos::NOUN,
+        UniversalPos::NUM,
+        UniversalPos::PART,
+        UniversalPos::PRON,
+        UniversalPos::PROPN,
+        UniversalPos::PUNCT,
+        UniversalPos::SCONJ,
+        UniversalPos::SYM,
+        UniversalPos::VERB,
+        UniversalPos::X,
+    ];
+}
+
+impl TagSet for UniversalPos {
+    const ALL: &'static [UniversalPos] = &UniversalPos::ALL;
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+
+    fn name(&self) -> &'static str {
+        use UniversalPos::*;
+        match self {
+            ADJ => "ADJ",
+            ADP => "ADP",
+            ADV => "ADV",
+            AUX => "AUX",
+            CCONJ => "CCONJ",
+            DET => "DET",
+            INTJ => "INTJ",
+            NOUN => "NOUN",
+            NUM => "NUM",
+            PART => "PART",
+            PRON => "PRON",
+            PROPN => "PROPN",
+            PUNCT => "PUNCT",
+            SCONJ => "SCONJ",
+            SYM => "SYM",
+            VERB => "VERB",
+            X => "X",
+        }
+    }
+
+    // Penn Treebank tags are mapped with PartOfSpeech::universal
+    fn from_conllu(word: &conllu::Word, column: TagColumn) -> Option<UniversalPos> {
+        match column {
+            TagColumn::Xpos => PartOfSpeech::from_treebank(&word.xpos).map(|tag| tag.universal()),
+            TagColumn::Upos => UniversalPos::from_name(&word.upos),
+        }
+    }
+}
+
+impl fmt::Display for UniversalPos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for UniversalPos {
+    type Err = io::Error;
+
+    fn from_str(tag: &str) -> io::Result<UniversalPos> {
+        UniversalPos::from_name(tag).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown Universal POS tag {:?}", tag),
+            )
+        })
+    }
+}
@@ -0,0 +1,283 @@
+use super::invalid_data;
+use super::lexemizer::normalize;
+use super::lexemizer::Lexeme;
+use super::vocab::Vocab;
+use super::vocab::VocabIndex;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::path::Path;
+
+// Static word vectors, keyed by VocabIndex
+// Words are normalized like lexemes, so "The" and "the" share an entry (the first one in the file wins)
+// The tagger doesn't use them: its features are all binary, see features::Template
+pub struct Vectors {
+    dim: usize,
+    data: Vec<f32>, // one row of dim floats per vector
+    norms: Vec<f32>,
+    rows: HashMap<VocabIndex, usize>,
+    indexes: Vec<VocabIndex>, // the VocabIndex of each row
+}
+
+impl Vectors {
+    pub fn new(dim: usize) -> Vectors {
+        Vectors {
+            dim,
+            data: Vec::new(),
+            norms: Vec::new(),
+            rows: HashMap::new(),
+            indexes: Vec::new(),
+        }
+    }
+
+    // Load word2vec's binary format: a "count dim" line, then per word
+    // the word, a space, and dim little endian f32s
+    // Words are added to the vocab. At most limit words are read
+    pub fn load_word2vec(
+        path: impl AsRef<Path>,
+        vocab: &mut Vocab,
+        limit: Option<usize>,
+    ) -> io::Result<Vectors> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let (count, dim) =
+            parse_header(&header).ok_or_else(|| invalid_data("bad word2vec header"))?;
+        if dim == 0 {
+            return Err(invalid_data("vector file has no dimensions"));
+        }
+        let mut vectors = Vectors::new(dim);
+        let mut vector = vec![0f32; dim];
+        let mut bytes = vec![0u8; dim * 4];
+        for _ in 0..limit.unwrap_or(count).min(count) {
+            let mut word = Vec::new();
+            reader.read_until(b' ', &mut word)?;
+            word.pop();
+            reader.read_exact(&mut bytes)?;
+            for (x, b) in vector.iter_mut().zip(bytes.chunks_exact(4)) {
+                *x = f32::from_le_bytes(b.try_into().unwrap());
+            }
+            // some writers put a newline after each vector, which we skip here
+            let word = String::from_utf8_lossy(&word);
+            let index = vocab
+                .intern(&normalize(word.trim_start()))
+                .ok_or_else(|| invalid_data("vocab has no ids left"))?;
+            vectors.insert(index, &vector)?;
+        }
+        Ok(vectors)
+    }
+
+    // Load a text format: one word per line followed by its values, separated by spaces
+    // This is GloVe, and fastText's .vec (which has an extra "count dim" header line)
+    // Words are added to the vocab. At most limit words are read
+    pub fn load_text(
+        path: impl AsRef<Path>,
+        vocab: &mut Vocab,
+        limit: Option<usize>,
+    ) -> io::Result<Vectors> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut vectors: Option<Vectors> = None;
+        let mut vector = Vec::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || (i == 0 && parse_header(&line).is_some()) {
+                continue;
+            }
+            if limit.is_some_and(|limit| vectors.as_ref().map_or(0, Vectors::len) >= limit) {
+                break;
+            }
+            // words may contain spaces in some GloVe files, so values are taken off the end
+            let fields: Vec<&str> = line.trim_end().split(' ').collect();
+            let dim = match vectors {
+                Some(ref vectors) => vectors.dim,
+                // the first line decides, so a line without values would make every word empty
+                None => match fields[1..]
+                    .iter()
+                    .rev()
+                    .take_while(|field| field.parse::<f32>().is_ok())
+                    .count()
+                {
+                    0 => return Err(invalid_data("vector file has no dimensions")),
+                    dim => dim,
+                },
+            };
+            if fields.len() <= dim {
+                return Err(invalid_data("vectors have different dimensions"));
+            }
+            let word = fields[..fields.len() - dim].join(" ");
+            vector.clear();
+            for field in &fields[fields.len() - dim..] {
+                vector.push(
+                    field
+                        .parse::<f32>()
+                        .map_err(|_| invalid_data("bad vector value"))?,
+                );
+            }
+            let vectors = vectors.get_or_insert_with(|| Vectors::new(vector.len()));
+            let index = vocab
+                .intern(&normalize(&word))
+                .ok_or_else(|| invalid_data("vocab has no ids left"))?;
+            vectors.insert(index, &vector)?;
+        }
+        Ok(vectors.unwrap_or_else(|| Vectors::new(0)))
+    }
+
+    pub fn load_glove(
+        path: impl AsRef<Path>,
+        vocab: &mut Vocab,
+        limit: Option<usize>,
+    ) -> io::Result<Vectors> {
+        Vectors::load_text(path, vocab, limit)
+    }
+
+    pub fn load_fasttext(
+        path: impl AsRef<Path>,
+        vocab: &mut Vocab,
+        limit: Option<usize>,
+    ) -> io::Result<Vectors> {
+        Vectors::load_text(path, vocab, limit)
+    }
+
+    // Returns false (and does nothing) if index already has a vector
+    // Fails if the vector isn't dim long
+    pub fn insert(&mut self, index: VocabIndex, vector: &[f32]) -> io::Result<bool> {
+        if vector.len() != self.dim {
+            return Err(invalid_data("vector has the wrong dimension"));
+        }
+        if self.rows.contains_key(&index) {
+            return Ok(false);
+        }
+        self.rows.insert(index, self.indexes.len());
+        self.indexes.push(index);
+        self.data.extend_from_slice(vector);
+        self.norms.push(norm(vector));
+        Ok(true)
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn len(&self) -> usize {
+        self.indexes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indexes.is_empty()
+    }
+
+    pub fn contains(&self, index: VocabIndex) -> bool {
+        self.rows.contains_key(&index)
+    }
+
+    pub fn get(&self, index: VocabIndex) -> Option<&[f32]> {
+        self.rows.get(&index).map(|row| self.row(*row))
+    }
+
+    fn row(&self, row: usize) -> &[f32] {
+        &self.data[row * self.dim..(row + 1) * self.dim]
+    }
+
+    // Cosine similarity, None if either has no vector
+    pub fn similarity(&self, a: VocabIndex, b: VocabIndex) -> Option<f32> {
+        let (a, b) = (*self.rows.get(&a)?, *self.rows.get(&b)?);
+        Some(cosine(
+            self.row(a),
+            self.norms[a],
+            self.row(b),
+            self.norms[b],
+        ))
+    }
+
+    // The n entries most similar to index, most similar first
+    pub fn most_similar(&self, index: VocabIndex, n: usize) -> Vec<(VocabIndex, f32)> {
+        match self.get(index) {
+            Some(vector) => self
+                .nearest(vector, n + 1)
+                .into_iter()
+                .filter(|(other, _)| *other != index)
+                .take(n)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // The n entries most similar to an arbitrary vector (e.g. an average), most similar first
+    pub fn nearest(&self, vector: &[f32], n: usize) -> Vec<(VocabIndex, f32)> {
+        let vector_norm = norm(vector);
+        let mut scored: Vec<(VocabIndex, f32)> = (0..self.len())
+            .map(|row| {
+                let similarity = cosine(vector, vector_norm, self.row(row), self.norms[row]);
+                (self.indexes[row], similarity)
+            })
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        scored.truncate(n);
+        scored
+    }
+
+    // Mean of the vectors of the given entries, skipping entries without one
+    // None if none of them have a vector
+    pub fn average(&self, indexes: impl IntoIterator<Item = VocabIndex>) -> Option<Vec<f32>> {
+        let mut sum = vec![0f32; self.dim];
+        let mut count = 0;
+        for vector in indexes.into_iter().filter_map(|index| self.get(index)) {
+            for (s, x) in sum.iter_mut().zip(vector) {
+                *s += x;
+            }
+            count += 1;
+        }
+        if count == 0 {
+            return None;
+        }
+        for s in sum.iter_mut() {
+            *s /= count as f32;
+        }
+        Some(sum)
+    }
+
+    // Average over a whole document
+    pub fn average_lexemes(&self, lexemes: &[Lexeme<VocabIndex>]) -> Option<Vec<f32>> {
+        self.average(lexemes.iter().map(|lexeme| lexeme.value))
+    }
+
+    // Average over the lexemes whose span lies within start..end
+    pub fn average_span(
+        &self,
+        lexemes: &[Lexeme<VocabIndex>],
+        start: usize,
+        end: usize,
+    ) -> Option<Vec<f32>> {
+        self.average(
+            lexemes
+                .iter()
+                .filter(|lexeme| lexeme.start >= start && lexeme.end <= end)
+                .map(|lexeme| lexeme.value),
+        )
+    }
+}
+
+fn norm(vector: &[f32]) -> f32 {
+    vector.iter().map(|x| x * x).sum::<f32>().sqrt()
+}
+
+fn cosine(a: &[f32], a_norm: f32, b: &[f32], b_norm: f32) -> f32 {
+    if a_norm == 0.0 || b_norm == 0.0 {
+        return 0.0;
+    }
+    a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>() / (a_norm * b_norm)
+}
+
+// "count dim"
+fn parse_header(line: &str) -> Option<(usize, usize)> {
+    let mut fields = line.split_whitespace();
+    let count = fields.next()?.parse().ok()?;
+    let dim = fields.next()?.parse().ok()?;
+    match fields.next() {
+        None => Some((count, dim)),
+        Some(_) => None,
+    }
+}
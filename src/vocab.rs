@@ -1,43 +1,676 @@
+use super::attributes::Attributes;
+use super::invalid_data;
+use super::lexemizer::Lexeme;
+use super::lexemizer::RuleSet;
+use super::Fnv;
 use bimap::BiMap;
+use memmap2::Mmap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
 
-pub struct VocabIndex(value);
+// On disk format, all integers little endian:
+//   magic "YGVOCAB\0"
+//   version u32, reserved u32
+//   count u64, counter u64, hash u64, documents u64
+//     (counter is the next id to assign, so greater than every entry's id)
+//   count entries sorted by id: id u64, offset u64, len u64, count u64, doc_count u64, cluster u64
+//     (offset is into the string data)
+//   count u64 entry positions, sorted by the bytes of the entry
+//   string data
+// The two tables let a memory mapped file be searched without building a map
+// Version 1 files have no documents field and no counts in their entries
+// Version 2 files have no clusters in their entries
+// Version 3 files hash their entries without the counter
+const MAGIC: &[u8; 8] = b"YGVOCAB\0";
+const VERSION: u32 = 4;
+
+#[derive(
+    Debug,
+    std::hash::Hash,
+    Clone,
+    Copy,
+    std::cmp::Eq,
+    std::cmp::PartialEq,
+    std::cmp::Ord,
+    std::cmp::PartialOrd,
+)]
+pub struct VocabIndex(pub u64);
+
+impl VocabIndex {
+    // Shared by every string that isn't in the vocab. Never assigned to an entry
+    pub const OOV: VocabIndex = VocabIndex(u64::MAX);
+}
+
+// How often an entry was seen in the corpus the vocab was counted over
+#[derive(Debug, Clone, Copy, Default, std::cmp::Eq, std::cmp::PartialEq)]
+pub struct Frequency {
+    pub count: u64,     // occurrences
+    pub doc_count: u64, // documents it occurred in
+}
+
+// How much of some text a vocab knows about
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Coverage {
+    pub tokens: u64,
+    pub known_tokens: u64,
+    pub types: u64,
+    pub known_types: u64,
+}
+
+impl Coverage {
+    pub fn token_coverage(&self) -> f64 {
+        self.known_tokens as f64 / self.tokens.max(1) as f64
+    }
+
+    pub fn type_coverage(&self) -> f64 {
+        self.known_types as f64 / self.types.max(1) as f64
+    }
+}
 
 pub struct Vocab {
     // use ascii lowercase
-    counter:u64,
-    values:BiMap<u64, Vec<u8>>,
+    counter: u64,
+    values: BiMap<u64, Vec<u8>>,
+    frequencies: HashMap<u64, Frequency>,
+    documents: u64,
+    attributes: HashMap<u64, Attributes>,
 }
 
 impl Vocab {
     pub fn new() -> Vocab {
         Vocab {
-            values: BiMap::new()
+            counter: 0,
+            values: BiMap::new(),
+            frequencies: HashMap::new(),
+            documents: 0,
+            attributes: HashMap::new(),
         }
     }
 
-    pub fn add(&mut self, value:Vec<u8>) -> Option<VocabIndex> {
-        match(self.values.insert_no_overwrite(self.counter, value)) {
+    // Adds a new value, returning None if it was already present or every id has been used
+    pub fn add(&mut self, value: Vec<u8>) -> Option<VocabIndex> {
+        if self.counter == VocabIndex::OOV.0 {
+            return None;
+        }
+        let attributes = Attributes::of(&value);
+        match self.values.insert_no_overwrite(self.counter, value) {
             Ok(()) => {
+                let index = VocabIndex(self.counter);
+                self.attributes.insert(self.counter, attributes);
                 self.counter += 1;
-                VocabIndex(self.counter)
-            },
-            Err(_, _) => None
+                Some(index)
+            }
+            Err(_) => None,
+        }
+    }
+
+    // Returns the index of value, adding it if it isn't present
+    // None if it needed adding, but every id has been used
+    pub fn intern(&mut self, value: &[u8]) -> Option<VocabIndex> {
+        match self.values.get_by_right(value) {
+            Some(index) => Some(VocabIndex(*index)),
+            None => self.add(value.to_vec()),
+        }
+    }
+
+    // Lexemes that can't be added become OOV
+    pub fn intern_lexemes(&mut self, lexemes: Vec<Lexeme>) -> Vec<Lexeme<VocabIndex>> {
+        lexemes
+            .into_iter()
+            .map(|lexeme| lexeme.map(|value| self.intern(&value).unwrap_or(VocabIndex::OOV)))
+            .collect()
+    }
+
+    // Returns the index of value, or OOV if it isn't present
+    pub fn lookup(&self, value: &[u8]) -> VocabIndex {
+        self.get_id_by_str(value).unwrap_or(VocabIndex::OOV)
+    }
+
+    // Like intern_lexemes, but unknown lexemes become OOV instead of being added
+    pub fn lookup_lexemes(&self, lexemes: Vec<Lexeme>) -> Vec<Lexeme<VocabIndex>> {
+        lexemes
+            .into_iter()
+            .map(|lexeme| lexeme.map(|value| self.lookup(&value)))
+            .collect()
+    }
+
+    // Intern and count the lexemes of one document
+    // Lexemes that can't be added aren't counted
+    pub fn count_document(&mut self, lexemes: &[Lexeme]) {
+        let mut seen = HashSet::new();
+        for lexeme in lexemes {
+            let index = match self.intern(&lexeme.value) {
+                Some(index) => index,
+                None => continue,
+            };
+            let frequency = self.frequencies.entry(index.0).or_default();
+            frequency.count += 1;
+            if seen.insert(index) {
+                frequency.doc_count += 1;
+            }
+        }
+        self.documents += 1;
+    }
+
+    // Lexemize and count every document of a corpus
+    pub fn count_corpus(&mut self, ruleset: &RuleSet, documents: impl IntoIterator<Item = String>) {
+        for document in documents {
+            self.count_document(&ruleset.lexemize(document));
+        }
+    }
+
+    pub fn frequency(&self, value: VocabIndex) -> Frequency {
+        self.frequencies.get(&value.0).copied().unwrap_or_default()
+    }
+
+    // Number of documents counted
+    pub fn documents(&self) -> u64 {
+        self.documents
+    }
+
+    // Merge counts made elsewhere (e.g. by a VocabHandle)
+    // Ignored if value was pruned in the meantime
+    pub(crate) fn add_frequency(&mut self, value: VocabIndex, frequency: Frequency) {
+        if self.contains(value) {
+            let total = self.frequencies.entry(value.0).or_default();
+            total.count += frequency.count;
+            total.doc_count += frequency.doc_count;
+        }
+    }
+
+    pub(crate) fn add_documents(&mut self, documents: u64) {
+        self.documents += documents;
+    }
+
+    // Remove every entry seen fewer than min_count times
+    // The remaining entries keep their ids. Returns how many were removed
+    pub fn prune_min_count(&mut self, min_count: u64) -> usize {
+        let rare: Vec<VocabIndex> = self
+            .iter()
+            .map(|(index, _)| index)
+            .filter(|index| self.frequency(*index).count < min_count)
+            .collect();
+        self.remove_all(&rare)
+    }
+
+    // Keep only the n most frequent entries, breaking ties by lowest id
+    // The remaining entries keep their ids. Returns how many were removed
+    pub fn prune_top_n(&mut self, n: usize) -> usize {
+        let mut entries: Vec<VocabIndex> = self.iter().map(|(index, _)| index).collect();
+        entries.sort_by_key(|index| (std::cmp::Reverse(self.frequency(*index).count), *index));
+        let rest = entries.split_off(n.min(entries.len()));
+        self.remove_all(&rest)
+    }
+
+    fn remove_all(&mut self, indexes: &[VocabIndex]) -> usize {
+        for index in indexes {
+            self.values.remove_by_left(&index.0);
+            self.frequencies.remove(&index.0);
+            self.attributes.remove(&index.0);
+        }
+        indexes.len()
+    }
+
+    // How many of the given lexemes (and distinct lexemes) this vocab knows
+    pub fn coverage(&self, lexemes: &[Lexeme]) -> Coverage {
+        let mut coverage = Coverage::default();
+        let mut types = HashSet::new();
+        for lexeme in lexemes {
+            let known = self.contains_by_str(&lexeme.value);
+            coverage.tokens += 1;
+            coverage.known_tokens += known as u64;
+            if types.insert(&lexeme.value) {
+                coverage.types += 1;
+                coverage.known_types += known as u64;
+            }
+        }
+        coverage
+    }
+
+    pub fn attributes(&self, value: VocabIndex) -> Option<&Attributes> {
+        self.attributes.get(&value.0)
+    }
+
+    // Returns false if value isn't in the vocab
+    pub fn set_cluster(&mut self, value: VocabIndex, cluster: u64) -> bool {
+        match self.attributes.get_mut(&value.0) {
+            Some(attributes) => {
+                attributes.cluster = cluster;
+                true
+            }
+            None => false,
         }
     }
 
-    pub fn get(&self, value:VocabIndex) -> Option<&Vec<u8>> {
-        self.values.get_by_left(value.0)
+    pub fn get(&self, value: VocabIndex) -> Option<&Vec<u8>> {
+        self.values.get_by_left(&value.0)
     }
 
     pub fn contains(&self, value: VocabIndex) -> bool {
-        self.values.contains_by_left(value.0)
+        self.values.contains_left(&value.0)
+    }
+
+    pub fn get_id_by_str(&self, value: &[u8]) -> Option<VocabIndex> {
+        self.values
+            .get_by_right(value)
+            .map(|index| VocabIndex(*index))
     }
 
-    pub fn get_id_by_str(&self, value:&Vec<u8>) -> VocabIndex {
-        VocabIndex(self.values.get_by_right(value))
+    pub fn contains_by_str(&self, value: &[u8]) -> bool {
+        self.values.contains_right(value)
     }
 
-    pub fn contains_by_str(&self, value: &Vec<u8>) ->bool {
-        self.values.contains_by_left(value)
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    // Entries in id order
+    pub fn iter(&self) -> impl Iterator<Item = (VocabIndex, &[u8])> {
+        let mut entries: Vec<(VocabIndex, &[u8])> = self
+            .values
+            .iter()
+            .map(|(index, value)| (VocabIndex(*index), value.as_slice()))
+            .collect();
+        entries.sort_unstable_by_key(|(index, _)| *index);
+        entries.into_iter()
+    }
+
+    // Fingerprint of every (id, string) pair, and the next id to assign
+    // Models store this, so that they are never used with a different vocab
+    pub fn hash(&self) -> u64 {
+        hash_entries(self.iter(), Some(self.counter))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut file = io::BufWriter::new(File::create(path)?);
+        self.write_to(&mut file)?;
+        file.flush()
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        let entries: Vec<(VocabIndex, &[u8])> = self.iter().collect();
+        let mut sorted: Vec<usize> = (0..entries.len()).collect();
+        sorted.sort_unstable_by_key(|i| entries[*i].1);
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&(entries.len() as u64).to_le_bytes())?;
+        writer.write_all(&self.counter.to_le_bytes())?;
+        writer.write_all(&self.hash().to_le_bytes())?;
+        writer.write_all(&self.documents.to_le_bytes())?;
+        let mut offset = 0u64;
+        for (index, value) in entries.iter() {
+            let frequency = self.frequency(*index);
+            writer.write_all(&index.0.to_le_bytes())?;
+            writer.write_all(&offset.to_le_bytes())?;
+            writer.write_all(&(value.len() as u64).to_le_bytes())?;
+            writer.write_all(&frequency.count.to_le_bytes())?;
+            writer.write_all(&frequency.doc_count.to_le_bytes())?;
+            let cluster = self
+                .attributes(*index)
+                .map_or(0, |attributes| attributes.cluster);
+            writer.write_all(&cluster.to_le_bytes())?;
+            offset += value.len() as u64;
+        }
+        for i in sorted {
+            writer.write_all(&(i as u64).to_le_bytes())?;
+        }
+        for (_, value) in entries.iter() {
+            writer.write_all(value)?;
+        }
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Vocab> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        Vocab::from_bytes(&bytes)
+    }
+
+    // Load, and make sure this is the vocab a model was trained with
+    pub fn load_checked(path: impl AsRef<Path>, expected_hash: u64) -> io::Result<Vocab> {
+        let vocab = Vocab::load(path)?;
+        check_hash(vocab.hash(), expected_hash)?;
+        Ok(vocab)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Vocab> {
+        let file = VocabFile::parse(bytes)?;
+        file.check_hash()?;
+        let mut values = BiMap::new();
+        let mut frequencies = HashMap::new();
+        let mut attributes = HashMap::new();
+        for i in 0..file.count {
+            let (index, value) = file.entry(i);
+            attributes.insert(
+                index.0,
+                Attributes {
+                    cluster: file.cluster(i),
+                    ..Attributes::of(value)
+                },
+            );
+            if values.insert_no_overwrite(index.0, value.to_vec()).is_err() {
+                return Err(invalid_data("duplicate vocab entry"));
+            }
+            let frequency = file.frequency(i);
+            if frequency != Frequency::default() {
+                frequencies.insert(index.0, frequency);
+            }
+        }
+        Ok(Vocab {
+            counter: file.counter(),
+            values,
+            frequencies,
+            documents: file.documents(),
+            attributes,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+// Just the size, as vocabs can have millions of entries
+impl fmt::Debug for Vocab {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Vocab")
+            .field("len", &self.len())
+            .field("documents", &self.documents)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Vocab {
+    fn default() -> Vocab {
+        Vocab::new()
+    }
+}
+
+// A read only vocab that is searched in place in a memory mapped file
+// Lookups are binary searches, so no memory is spent on a map
+pub struct MappedVocab {
+    mmap: Mmap,
+    hash: u64,
+    // the layout of the file, from VocabFile::parse
+    version: u32,
+    count: usize,
+    entries_start: usize,
+    entry_len: usize,
+    data_start: usize,
+}
+
+impl MappedVocab {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<MappedVocab> {
+        let file = File::open(path)?;
+        // Safety: the file must not be modified while it is mapped
+        let mmap = unsafe { Mmap::map(&file)? };
+        let file = VocabFile::parse(&mmap)?;
+        let hash = file.check_hash()?;
+        let VocabFile {
+            version,
+            count,
+            entries_start,
+            entry_len,
+            data_start,
+            ..
+        } = file;
+        Ok(MappedVocab {
+            mmap,
+            hash,
+            version,
+            count,
+            entries_start,
+            entry_len,
+            data_start,
+        })
+    }
+
+    pub fn open_checked(path: impl AsRef<Path>, expected_hash: u64) -> io::Result<MappedVocab> {
+        let vocab = MappedVocab::open(path)?;
+        check_hash(vocab.hash, expected_hash)?;
+        Ok(vocab)
+    }
+
+    // Validated once in open, so lookups don't parse the file again
+    fn file(&self) -> VocabFile<'_> {
+        VocabFile {
+            bytes: &self.mmap,
+            version: self.version,
+            count: self.count,
+            entries_start: self.entries_start,
+            entry_len: self.entry_len,
+            data_start: self.data_start,
+        }
+    }
+
+    pub fn get(&self, value: VocabIndex) -> Option<&[u8]> {
+        let file = self.file();
+        let i = binary_search(self.count, |i| file.entry(i).0.cmp(&value))?;
+        Some(file.entry(i).1)
+    }
+
+    pub fn contains(&self, value: VocabIndex) -> bool {
+        self.get(value).is_some()
+    }
+
+    pub fn get_id_by_str(&self, value: &[u8]) -> Option<VocabIndex> {
+        let file = self.file();
+        let i = binary_search(self.count, |i| file.entry(file.sorted(i)).1.cmp(value))?;
+        Some(file.entry(file.sorted(i)).0)
+    }
+
+    pub fn contains_by_str(&self, value: &[u8]) -> bool {
+        self.get_id_by_str(value).is_some()
+    }
+
+    pub fn lookup(&self, value: &[u8]) -> VocabIndex {
+        self.get_id_by_str(value).unwrap_or(VocabIndex::OOV)
+    }
+
+    // Computed on every call, as nothing is cached for a mapped vocab
+    pub fn attributes(&self, value: VocabIndex) -> Option<Attributes> {
+        let file = self.file();
+        let i = binary_search(self.count, |i| file.entry(i).0.cmp(&value))?;
+        Some(Attributes {
+            cluster: file.cluster(i),
+            ..Attributes::of(file.entry(i).1)
+        })
+    }
+
+    pub fn frequency(&self, value: VocabIndex) -> Frequency {
+        let file = self.file();
+        binary_search(self.count, |i| file.entry(i).0.cmp(&value))
+            .map(|i| file.frequency(i))
+            .unwrap_or_default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    // Copy into a mutable vocab
+    pub fn to_vocab(&self) -> io::Result<Vocab> {
+        Vocab::from_bytes(&self.mmap)
+    }
+}
+
+// A validated view of the on disk format
+struct VocabFile<'a> {
+    bytes: &'a [u8],
+    version: u32,
+    count: usize,
+    entries_start: usize,
+    entry_len: usize,
+    data_start: usize,
+}
+
+impl<'a> VocabFile<'a> {
+    fn parse(bytes: &'a [u8]) -> io::Result<VocabFile<'a>> {
+        if bytes.len() < 16 || &bytes[..8] != MAGIC {
+            return Err(invalid_data("not a vocab file"));
+        }
+        let version = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        let (entries_start, entry_len) = match version {
+            1 => (40, 24),
+            2 => (48, 40),
+            3 | 4 => (48, 48),
+            _ => return Err(invalid_data("unsupported vocab file version")),
+        };
+        if bytes.len() < entries_start {
+            return Err(invalid_data("vocab file is truncated"));
+        }
+        let count = read_u64(bytes, 16) as usize;
+        let data_start = count
+            .checked_mul(entry_len + 8)
+            .and_then(|len| len.checked_add(entries_start))
+            .filter(|start| *start <= bytes.len())
+            .ok_or_else(|| invalid_data("vocab file is truncated"))?;
+        let file = VocabFile {
+            bytes,
+            version,
+            count,
+            entries_start,
+            entry_len,
+            data_start,
+        };
+        let data_len = (bytes.len() - data_start) as u64;
+        for i in 0..count {
+            let at = entries_start + i * entry_len;
+            let (offset, len) = (read_u64(bytes, at + 8), read_u64(bytes, at + 16));
+            if offset.checked_add(len).is_none_or(|end| end > data_len)
+                || (i > 0 && read_u64(bytes, at - entry_len) >= read_u64(bytes, at))
+                || file.sorted(i) >= count
+            {
+                return Err(invalid_data("vocab file is corrupt"));
+            }
+        }
+        // ids are ascending, so the last is the largest
+        if count > 0 && file.counter() <= file.entry(count - 1).0 .0 {
+            return Err(invalid_data("vocab file is corrupt"));
+        }
+        Ok(file)
+    }
+
+    fn counter(&self) -> u64 {
+        read_u64(self.bytes, 24)
+    }
+
+    // Returns the hash Vocab::hash gives for these entries, which for version 4 files is the one
+    // stored. Older files store a hash without the counter, which is checked instead
+    fn check_hash(&self) -> io::Result<u64> {
+        let entries = || (0..self.count).map(|i| self.entry(i));
+        let hash = hash_entries(entries(), Some(self.counter()));
+        let stored = match self.version {
+            1..=3 => hash_entries(entries(), None),
+            _ => hash,
+        };
+        if stored != read_u64(self.bytes, 32) {
+            return Err(invalid_data("vocab file is corrupt"));
+        }
+        Ok(hash)
+    }
+
+    fn documents(&self) -> u64 {
+        if self.version < 2 {
+            return 0;
+        }
+        read_u64(self.bytes, 40)
+    }
+
+    // i-th entry in id order
+    fn entry(&self, i: usize) -> (VocabIndex, &'a [u8]) {
+        let at = self.entries_start + i * self.entry_len;
+        let offset = self.data_start + read_u64(self.bytes, at + 8) as usize;
+        let len = read_u64(self.bytes, at + 16) as usize;
+        (
+            VocabIndex(read_u64(self.bytes, at)),
+            &self.bytes[offset..offset + len],
+        )
+    }
+
+    fn frequency(&self, i: usize) -> Frequency {
+        if self.version < 2 {
+            return Frequency::default();
+        }
+        let at = self.entries_start + i * self.entry_len;
+        Frequency {
+            count: read_u64(self.bytes, at + 24),
+            doc_count: read_u64(self.bytes, at + 32),
+        }
+    }
+
+    fn cluster(&self, i: usize) -> u64 {
+        if self.version < 3 {
+            return 0;
+        }
+        read_u64(self.bytes, self.entries_start + i * self.entry_len + 40)
+    }
+
+    // position of the i-th entry in byte order
+    fn sorted(&self, i: usize) -> usize {
+        read_u64(
+            self.bytes,
+            self.entries_start + self.count * self.entry_len + i * 8,
+        ) as usize
+    }
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+fn binary_search(len: usize, cmp: impl Fn(usize) -> std::cmp::Ordering) -> Option<usize> {
+    let (mut lo, mut hi) = (0, len);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        match cmp(mid) {
+            std::cmp::Ordering::Less => lo = mid + 1,
+            std::cmp::Ordering::Greater => hi = mid,
+            std::cmp::Ordering::Equal => return Some(mid),
+        }
+    }
+    None
+}
+
+fn hash_entries<'a>(
+    entries: impl Iterator<Item = (VocabIndex, &'a [u8])>,
+    counter: Option<u64>,
+) -> u64 {
+    let mut hasher = Fnv::default();
+    for (index, value) in entries {
+        hasher.write(&index.0.to_le_bytes());
+        hasher.write(&(value.len() as u64).to_le_bytes());
+        hasher.write(value);
+    }
+    if let Some(counter) = counter {
+        hasher.write(&counter.to_le_bytes());
+    }
+    hasher.finish()
+}
+
+fn check_hash(hash: u64, expected_hash: u64) -> io::Result<()> {
+    if hash == expected_hash {
+        Ok(())
+    } else {
+        Err(invalid_data(
+            "vocab does not match the one the model was built with",
+        ))
     }
 }
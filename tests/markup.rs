@@ -0,0 +1,78 @@
+use yogurt::markup::BlockKind;
+use yogurt::markup::Document;
+
+fn blocks(document: &Document) -> Vec<(BlockKind, &str)> {
+    document
+        .blocks
+        .iter()
+        .map(|block| (block.kind, document.block_text(block)))
+        .collect()
+}
+
+#[test]
+fn heading_keeps_a_hash_that_ends_a_word() {
+    let document = Document::from_markdown("# Learning C#\n\n## F# ##\n\n### Notes ###   \n\n#\n");
+    assert_eq!(
+        blocks(&document),
+        vec![
+            (BlockKind::Heading(1), "Learning C#"),
+            (BlockKind::Heading(2), "F#"),
+            (BlockKind::Heading(3), "Notes"),
+        ]
+    );
+}
+
+#[test]
+fn setext_heading_and_paragraph() {
+    let document = Document::from_markdown("Title\n=====\n\nSome *text*\nover two lines\n");
+    assert_eq!(document.blocks.len(), 2);
+    assert_eq!(document.blocks[0].kind, BlockKind::Heading(1));
+    assert_eq!(document.block_text(&document.blocks[0]).trim(), "Title");
+    assert_eq!(document.blocks[1].kind, BlockKind::Paragraph);
+    let text = document.block_text(&document.blocks[1]);
+    assert!(
+        text.contains("Some text") && text.contains("over two lines"),
+        "{}",
+        text
+    );
+}
+
+#[test]
+fn script_and_style_bodies_are_skipped() {
+    let raw = "<p>before</p>\
+        <script>if (a<b) { s = \"</p>\"; }</script>\
+        <style>p > a { content: '<' }</STYLE >\
+        <p>after</p>";
+    let document = Document::from_html(raw);
+    assert_eq!(
+        blocks(&document),
+        vec![
+            (BlockKind::Paragraph, "before"),
+            (BlockKind::Paragraph, "after"),
+        ]
+    );
+}
+
+#[test]
+fn script_without_closing_tag_skips_the_rest() {
+    let document = Document::from_html("<p>kept</p><script>var x = 1; <p>lost</p>");
+    assert_eq!(blocks(&document), vec![(BlockKind::Paragraph, "kept")]);
+}
+
+#[test]
+fn text_maps_back_to_the_raw_document() {
+    let raw = "<h2>Caf&#233; <b>menu</b></h2>";
+    let document = Document::from_html(raw);
+    assert_eq!(
+        blocks(&document),
+        vec![(BlockKind::Heading(2), "Café menu")]
+    );
+    let block = document.blocks[0];
+    let text = document.block_text(&block);
+    let menu = block.start + text.find("menu").unwrap();
+    let (start, end) = document.raw_span(menu, menu + 4);
+    assert_eq!(&raw[start..end], "menu");
+    let cafe = block.start + text.find("Café").unwrap();
+    let (start, end) = document.raw_span(cafe, cafe + "Café".len());
+    assert_eq!(&raw[start..end], "Caf&#233;");
+}
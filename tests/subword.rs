@@ -0,0 +1,90 @@
+use yogurt::lexemizer::Case;
+use yogurt::lexemizer::Lexeme;
+use yogurt::subword::Algorithm;
+use yogurt::subword::Subwords;
+use yogurt::vocab::Vocab;
+
+fn lexeme(value: &[u8]) -> Lexeme {
+    Lexeme {
+        value: value.to_vec(),
+        start: 0,
+        end: value.len(),
+        case: Case::Lower,
+    }
+}
+
+// Includes values that have to be escaped in the file
+fn vocab() -> Vocab {
+    let mut vocab = Vocab::new();
+    let words: &[&[u8]] = &[
+        b"lower",
+        b"lowest",
+        b"newer",
+        b"newest",
+        b"wider",
+        b"widest",
+        b"low",
+        b"new",
+        b"a\\b",
+        b"x y",
+        b"\x01\xff",
+    ];
+    for _ in 0..3 {
+        vocab.count_document(&words.iter().map(|word| lexeme(word)).collect::<Vec<_>>());
+    }
+    vocab
+}
+
+fn to_bytes(subwords: &Subwords) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    subwords.write_to(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn subwords_round_trip() {
+    let vocab = vocab();
+    for algorithm in [Algorithm::Bpe, Algorithm::WordPiece] {
+        let subwords = Subwords::train(algorithm, &vocab, 40, 1);
+        let bytes = to_bytes(&subwords);
+        let loaded = Subwords::read_from(&bytes[..]).unwrap();
+        assert_eq!(loaded.algorithm(), algorithm);
+        assert_eq!(loaded.len(), subwords.len());
+        assert_eq!(to_bytes(&loaded), bytes);
+        for (_, value) in vocab.iter() {
+            let ids = loaded.encode(value);
+            assert_eq!(ids, subwords.encode(value));
+            assert_eq!(loaded.decode(&ids), vec![value.to_vec()]);
+        }
+    }
+}
+
+#[test]
+fn merges_shorten_frequent_words() {
+    let subwords = Subwords::train_bpe(&vocab(), 40, 1);
+    assert!(subwords.encode(b"lowest").len() < b"lowest".len());
+    // bytes never seen in training can't be encoded
+    assert_eq!(subwords.encode(b"q"), vec![yogurt::subword::UNKNOWN]);
+}
+
+#[test]
+fn corrupt_subword_files_are_rejected() {
+    let bytes = to_bytes(&Subwords::train_wordpiece(&vocab(), 40, 1));
+    let text = String::from_utf8(bytes).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    let corrupt = [
+        text.replacen("yogurt-subwords 1", "yogurt-subwords 2", 1),
+        text.replacen("algorithm wordpiece", "algorithm unigram", 1),
+        // truncated
+        lines[..lines.len() - 1].join("\n"),
+        // a merge of a piece that doesn't exist
+        format!("{}\n{} 0", lines[..lines.len() - 1].join("\n"), u32::MAX),
+        // pieces swapped
+        [&lines[..3], &[lines[4], lines[3]], &lines[5..]]
+            .concat()
+            .join("\n"),
+    ];
+    for text in corrupt {
+        assert!(Subwords::read_from(text.as_bytes()).is_err(), "{}", text);
+    }
+}
@@ -0,0 +1,275 @@
+use yogurt::decoder::Decoder;
+use yogurt::features::Template;
+use yogurt::lexemizer::Case;
+use yogurt::lexemizer::Lexeme;
+use yogurt::tokenizer::Hashing;
+use yogurt::tokenizer::PartOfSpeech;
+use yogurt::tokenizer::Tagger;
+use yogurt::tokenizer::TrainOptions;
+
+const CORPUS: &[&str] = &[
+    "the/DT dog/NN barks/VBZ ./.",
+    "a/DT cat/NN sleeps/VBZ on/IN the/DT mat/NN ./.",
+    "the/DT dogs/NNS bark/VBP at/IN a/DT cat/NN ./.",
+    "cats/NNS will/MD sleep/VB ./.",
+    "the/DT old/JJ dog/NN walked/VBD home/NN ./.",
+];
+
+// "word/TAG word/TAG ..." as lowercase lexemes
+fn sentence(text: &str) -> Vec<(Lexeme, PartOfSpeech)> {
+    let mut start = 0;
+    text.split(' ')
+        .map(|pair| {
+            let (word, tag) = pair.rsplit_once('/').unwrap();
+            let lexeme = Lexeme {
+                value: word.as_bytes().to_vec(),
+                start,
+                end: start + word.len(),
+                case: Case::Lower,
+            };
+            start = lexeme.end + 1;
+            (lexeme, PartOfSpeech::from_treebank(tag).unwrap())
+        })
+        .collect()
+}
+
+fn corpus() -> Vec<Vec<(Lexeme, PartOfSpeech)>> {
+    CORPUS.iter().map(|text| sentence(text)).collect()
+}
+
+fn lexemes(sentence: &[(Lexeme, PartOfSpeech)]) -> Vec<Lexeme> {
+    sentence.iter().map(|(lexeme, _)| lexeme.clone()).collect()
+}
+
+fn to_bytes(tagger: &Tagger) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    tagger.write_to(&mut bytes).unwrap();
+    bytes
+}
+
+fn round_trip(options: &TrainOptions) {
+    let corpus = corpus();
+    let tagger: Tagger = Tagger::train(&corpus, options).unwrap();
+    let bytes = to_bytes(&tagger);
+    let loaded: Tagger = Tagger::from_bytes(&bytes).unwrap();
+    assert_eq!(loaded.hashing(), tagger.hashing());
+    assert_eq!(loaded.templates(), tagger.templates());
+    assert_eq!(loaded.dictionary().len(), tagger.dictionary().len());
+    assert_eq!(to_bytes(&loaded), bytes);
+    for sentence in corpus.iter() {
+        assert_eq!(
+            loaded.tag(&lexemes(sentence)),
+            tagger.tag(&lexemes(sentence))
+        );
+    }
+}
+
+#[test]
+fn interned_model_round_trips() {
+    round_trip(&TrainOptions::default());
+}
+
+#[test]
+fn hashed_model_round_trips() {
+    round_trip(&TrainOptions {
+        hashing: Some(Hashing { bits: 8, seed: 3 }),
+        ..TrainOptions::default()
+    });
+}
+
+#[test]
+fn model_without_dictionary_round_trips() {
+    round_trip(&TrainOptions {
+        dictionary: None,
+        ..TrainOptions::default()
+    });
+}
+
+#[test]
+fn truncated_model_is_rejected() {
+    let tagger: Tagger = Tagger::train(&corpus(), &TrainOptions::default()).unwrap();
+    let bytes = to_bytes(&tagger);
+    for len in [0, 8, 12, bytes.len() / 2, bytes.len() - 1] {
+        assert!(Tagger::<PartOfSpeech>::from_bytes(&bytes[..len]).is_err());
+    }
+}
+
+#[test]
+fn hashed_model_with_too_many_rows_is_rejected() {
+    let options = TrainOptions {
+        hashing: Some(Hashing { bits: 4, seed: 0 }),
+        ..TrainOptions::default()
+    };
+    let tagger: Tagger = Tagger::train(&corpus(), &options).unwrap();
+    let bytes = to_bytes(&tagger);
+    // the bits come before the seed and the weights, which end the file
+    let weights = (1 << 4) * PartOfSpeech::COUNT * 4;
+    let bits = bytes.len() - weights - 9;
+    assert_eq!(bytes[bits], 4);
+    for bits_value in [Hashing::MAX_BITS, Hashing::MAX_BITS + 1, 32, 255] {
+        let mut corrupt = bytes.clone();
+        corrupt[bits] = bits_value;
+        assert!(Tagger::<PartOfSpeech>::from_bytes(&corrupt).is_err());
+    }
+    // a file cut off part way through the weights
+    let mut corrupt = bytes[..bits + 9].to_vec();
+    corrupt[bits] = 32;
+    assert!(Tagger::<PartOfSpeech>::from_bytes(&corrupt).is_err());
+}
+
+#[test]
+fn templates_a_model_couldnt_load_are_rejected() {
+    let corpus = corpus();
+    for template in [
+        Template::Suffix(0, 0),
+        Template::Suffix(0, 8),
+        Template::Prefix(-1, 0),
+        Template::Prefix(1, 9),
+        Template::Tag(-3),
+        Template::Tag(0),
+        Template::Cluster(0, 64),
+    ] {
+        let options = TrainOptions {
+            templates: vec![Template::Bias, template],
+            ..TrainOptions::default()
+        };
+        assert!(Tagger::<PartOfSpeech>::train(&corpus, &options).is_err());
+    }
+    // while every template in range trains, and loads again
+    let options = TrainOptions {
+        templates: vec![
+            Template::Suffix(0, 1),
+            Template::Prefix(1, 7),
+            Template::Tag(-2),
+            Template::Cluster(-1, 63),
+        ],
+        ..TrainOptions::default()
+    };
+    let tagger: Tagger = Tagger::train(&corpus, &options).unwrap();
+    let loaded: Tagger = Tagger::from_bytes(&to_bytes(&tagger)).unwrap();
+    assert_eq!(loaded.templates(), &options.templates[..]);
+}
+
+// Sentences where can, saw and book are nouns or verbs depending on the words before them
+const AMBIGUOUS: &[&str] = &[
+    "i/PRP can/MD {verb}/VB the/DT {noun}/NN ./.",
+    "the/DT can/NN is/VBZ near/IN the/DT {noun}/NN ./.",
+    "we/PRP saw/VBD a/DT {noun}/NN ./.",
+    "a/DT saw/NN cuts/VBZ the/DT {noun}/NN ./.",
+    "they/PRP book/VBP the/DT {noun}/NN ./.",
+    "the/DT book/NN is/VBZ on/IN the/DT {noun}/NN ./.",
+    "they/PRP {verb}/VBP the/DT {noun}/NN ./.",
+];
+
+fn ambiguous_corpus(nouns: &[&str], verbs: &[&str]) -> Vec<Vec<(Lexeme, PartOfSpeech)>> {
+    let mut sentences = Vec::new();
+    for (i, pattern) in AMBIGUOUS.iter().enumerate() {
+        for (j, noun) in nouns.iter().enumerate() {
+            // each noun with a different verb in each pattern, and no noun or verb in enough
+            // sentences for the tag dictionary, so the perceptron has to learn them
+            let verb = verbs[(i + j) % verbs.len()];
+            let text = pattern.replace("{noun}", noun).replace("{verb}", verb);
+            sentences.push(sentence(&text));
+        }
+    }
+    sentences
+}
+
+// Tags sentences made of nouns and verbs the tagger wasn't trained on, so only the context
+// the ambiguous words are in can decide their tags
+fn check_ambiguous_words(options: &TrainOptions) {
+    let nouns = [
+        "dog", "cat", "table", "car", "door", "chair", "box", "window", "cup", "wall",
+    ];
+    let verbs = ["see", "open", "paint", "move", "clean", "close"];
+    let train = ambiguous_corpus(&nouns, &verbs);
+    let test = ambiguous_corpus(&["tree", "house", "road"], &["wash", "fix"]);
+    let tagger: Tagger = Tagger::train(&train, options).unwrap();
+    let (mut tokens, mut correct) = (0, 0);
+    for sentence in test.iter() {
+        let tags = tagger.tag(&lexemes(sentence));
+        for ((lexeme, gold), tag) in sentence.iter().zip(tags) {
+            if [&b"can"[..], b"saw", b"book"].contains(&lexeme.value.as_slice()) {
+                assert_eq!(tag, *gold, "{:?}", sentence);
+            }
+            tokens += 1;
+            correct += (tag == *gold) as usize;
+        }
+    }
+    assert!(
+        correct as f64 / tokens as f64 >= 0.95,
+        "{} of {} correct with {:?}",
+        correct,
+        tokens,
+        options.decoder
+    );
+}
+
+#[test]
+fn greedy_tagger_uses_context() {
+    check_ambiguous_words(&TrainOptions::default());
+}
+
+#[test]
+fn beam_tagger_uses_context() {
+    check_ambiguous_words(&TrainOptions {
+        decoder: Decoder::Beam(4),
+        ..TrainOptions::default()
+    });
+}
+
+#[test]
+fn viterbi_tagger_uses_context() {
+    check_ambiguous_words(&TrainOptions {
+        decoder: Decoder::Viterbi,
+        templates: viterbi_templates(),
+        ..TrainOptions::default()
+    });
+}
+
+#[test]
+fn hashed_tagger_uses_context() {
+    check_ambiguous_words(&TrainOptions {
+        hashing: Some(Hashing { bits: 16, seed: 0 }),
+        ..TrainOptions::default()
+    });
+}
+
+#[test]
+fn parallel_tagger_uses_context() {
+    check_ambiguous_words(&TrainOptions {
+        threads: 3,
+        ..TrainOptions::default()
+    });
+}
+
+fn viterbi_templates() -> Vec<Template> {
+    Template::groups(&[
+        Template::WORDS,
+        Template::AFFIXES,
+        Template::ORTHOGRAPHY,
+        &[Template::Tag(-1)],
+    ])
+}
+
+#[test]
+fn decoder_is_saved_with_the_model() {
+    let corpus = corpus();
+    for (decoder, templates) in [
+        (Decoder::Greedy, Template::standard()),
+        (Decoder::Beam(3), Template::standard()),
+        (Decoder::Viterbi, viterbi_templates()),
+    ] {
+        let options = TrainOptions {
+            decoder,
+            templates,
+            ..TrainOptions::default()
+        };
+        let tagger: Tagger = Tagger::train(&corpus, &options).unwrap();
+        let mut loaded: Tagger = Tagger::from_bytes(&to_bytes(&tagger)).unwrap();
+        assert_eq!(loaded.decoder(), decoder);
+        loaded.set_decoder(Decoder::Beam(2));
+        let reloaded: Tagger = Tagger::from_bytes(&to_bytes(&loaded)).unwrap();
+        assert_eq!(reloaded.decoder(), Decoder::Beam(2));
+    }
+}
@@ -0,0 +1,87 @@
+use std::path::PathBuf;
+use yogurt::vectors::Vectors;
+use yogurt::vocab::Vocab;
+
+// A file of its own for each test, as they run in parallel
+fn write_temp(name: &str, bytes: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("yogurt-{}-{}.vec", std::process::id(), name));
+    std::fs::write(&path, bytes).unwrap();
+    path
+}
+
+fn load_text(name: &str, text: &str) -> (std::io::Result<Vectors>, Vocab) {
+    let path = write_temp(name, text.as_bytes());
+    let mut vocab = Vocab::new();
+    let vectors = Vectors::load_text(&path, &mut vocab, None);
+    std::fs::remove_file(&path).unwrap();
+    (vectors, vocab)
+}
+
+#[test]
+fn glove_and_fasttext_files_load() {
+    for (name, text) in [
+        ("glove", "the 1 0 0\ncat 0 1 0\ndog 0 0.9 0.1\n"),
+        ("fasttext", "3 3\nthe 1 0 0\ncat 0 1 0\ndog 0 0.9 0.1\n"),
+    ] {
+        let (vectors, vocab) = load_text(name, text);
+        let vectors = vectors.unwrap();
+        assert_eq!((vectors.dim(), vectors.len()), (3, 3));
+        let (cat, dog) = (vocab.lookup(b"cat"), vocab.lookup(b"dog"));
+        assert_eq!(vectors.get(cat), Some(&[0.0, 1.0, 0.0][..]));
+        assert_eq!(vectors.most_similar(cat, 1)[0].0, dog);
+    }
+}
+
+#[test]
+fn words_with_spaces_keep_them() {
+    let (vectors, vocab) = load_text("spaces", "new york 1 2\nthe 3 4\n");
+    let vectors = vectors.unwrap();
+    assert_eq!(vectors.dim(), 2);
+    assert_eq!(
+        vectors.get(vocab.lookup(b"new york")),
+        Some(&[1.0, 2.0][..])
+    );
+}
+
+#[test]
+fn text_file_without_values_is_rejected() {
+    for (name, text) in [("words", "the\ncat\n"), ("header", "2 3\nthe\ncat\n")] {
+        let (vectors, vocab) = load_text(name, text);
+        assert!(vectors.is_err());
+        assert!(vocab.is_empty());
+    }
+}
+
+#[test]
+fn text_file_with_different_dimensions_is_rejected() {
+    let (vectors, _) = load_text("ragged", "the 1 0 0\ncat 0 1\n");
+    assert!(vectors.is_err());
+}
+
+#[test]
+fn word2vec_file_loads() {
+    let mut bytes = b"2 2\n".to_vec();
+    for (word, vector) in [("the", [1f32, 0.0]), ("cat", [0.5, 0.5])] {
+        bytes.extend_from_slice(word.as_bytes());
+        bytes.push(b' ');
+        for x in vector {
+            bytes.extend_from_slice(&x.to_le_bytes());
+        }
+        bytes.push(b'\n');
+    }
+    let path = write_temp("word2vec", &bytes);
+    let mut vocab = Vocab::new();
+    let vectors = Vectors::load_word2vec(&path, &mut vocab, None).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!((vectors.dim(), vectors.len()), (2, 2));
+    assert_eq!(vectors.get(vocab.lookup(b"cat")), Some(&[0.5, 0.5][..]));
+}
+
+#[test]
+fn word2vec_file_without_dimensions_is_rejected() {
+    let path = write_temp("word2vec-empty", b"2 0\nthe \ncat \n");
+    let mut vocab = Vocab::new();
+    assert!(Vectors::load_word2vec(&path, &mut vocab, None).is_err());
+    std::fs::remove_file(&path).unwrap();
+    assert!(vocab.is_empty());
+}
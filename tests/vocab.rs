@@ -0,0 +1,163 @@
+use std::path::PathBuf;
+use yogurt::lexemizer::Case;
+use yogurt::lexemizer::Lexeme;
+use yogurt::vocab::Frequency;
+use yogurt::vocab::MappedVocab;
+use yogurt::vocab::Vocab;
+use yogurt::vocab::VocabIndex;
+
+fn lexemes(text: &str) -> Vec<Lexeme> {
+    let mut start = 0;
+    text.split(' ')
+        .map(|word| {
+            let lexeme = Lexeme {
+                value: word.as_bytes().to_vec(),
+                start,
+                end: start + word.len(),
+                case: Case::Lower,
+            };
+            start = lexeme.end + 1;
+            lexeme
+        })
+        .collect()
+}
+
+fn vocab() -> Vocab {
+    let mut vocab = Vocab::new();
+    vocab.count_document(&lexemes("the cat sat on the mat"));
+    vocab.count_document(&lexemes("the dog sat"));
+    vocab.count_document(&lexemes("a bird"));
+    // leaves a gap in the ids, and the counter past the last one
+    vocab.prune_min_count(2);
+    let sat = vocab.lookup(b"sat");
+    vocab.set_cluster(sat, 0b101);
+    vocab
+}
+
+fn to_bytes(vocab: &Vocab) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    vocab.write_to(&mut bytes).unwrap();
+    bytes
+}
+
+// A file of its own for each test, as they run in parallel
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("yogurt-{}-{}.vocab", std::process::id(), name))
+}
+
+// Vocab files store a FNV-1a hash, which the corrupt files below have to match
+fn fnv(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn set_u64(bytes: &mut [u8], at: usize, value: u64) {
+    bytes[at..at + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+#[test]
+fn vocab_round_trips() {
+    let vocab = vocab();
+    let bytes = to_bytes(&vocab);
+    let loaded = Vocab::from_bytes(&bytes).unwrap();
+    assert_eq!(loaded.len(), 2);
+    assert_eq!(loaded.hash(), vocab.hash());
+    assert_eq!(loaded.documents(), 3);
+    assert_eq!(
+        loaded.iter().collect::<Vec<_>>(),
+        vocab.iter().collect::<Vec<_>>()
+    );
+    let sat = loaded.lookup(b"sat");
+    assert_eq!(
+        loaded.frequency(sat),
+        Frequency {
+            count: 2,
+            doc_count: 2
+        }
+    );
+    assert_eq!(loaded.attributes(sat).unwrap().cluster, 0b101);
+    assert_eq!(loaded.lookup(b"cat"), VocabIndex::OOV);
+    assert_eq!(to_bytes(&loaded), bytes);
+}
+
+#[test]
+fn interning_after_loading_never_reuses_an_id() {
+    let vocab = vocab();
+    let mut loaded = Vocab::from_bytes(&to_bytes(&vocab)).unwrap();
+    let ids: Vec<VocabIndex> = loaded.iter().map(|(index, _)| index).collect();
+    let bird = loaded.intern(b"bird").unwrap();
+    assert!(ids.iter().all(|index| *index < bird));
+    assert_eq!(loaded.intern(b"bird"), Some(bird));
+    assert_ne!(loaded.hash(), vocab.hash());
+}
+
+#[test]
+fn mapped_vocab_matches_loaded_vocab() {
+    let vocab = vocab();
+    let path = temp_path("mapped");
+    vocab.save(&path).unwrap();
+    let mapped = MappedVocab::open_checked(&path, vocab.hash()).unwrap();
+    assert_eq!(mapped.len(), vocab.len());
+    for (index, value) in vocab.iter() {
+        assert_eq!(mapped.get(index), Some(value));
+        assert_eq!(mapped.lookup(value), index);
+        assert_eq!(mapped.frequency(index), vocab.frequency(index));
+        assert_eq!(
+            mapped.attributes(index).unwrap().cluster,
+            vocab.attributes(index).unwrap().cluster
+        );
+    }
+    assert_eq!(mapped.lookup(b"cat"), VocabIndex::OOV);
+    assert_eq!(mapped.to_vocab().unwrap().hash(), vocab.hash());
+    assert!(MappedVocab::open_checked(&path, vocab.hash() ^ 1).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn corrupt_vocab_is_rejected() {
+    let bytes = to_bytes(&vocab());
+    for len in [0, 16, 40, bytes.len() - 1] {
+        assert!(Vocab::from_bytes(&bytes[..len]).is_err());
+    }
+    // the string data is last, and covered by the hash
+    let mut corrupt = bytes.clone();
+    *corrupt.last_mut().unwrap() ^= 1;
+    assert!(Vocab::from_bytes(&corrupt).is_err());
+    // as is the counter
+    let mut corrupt = bytes.clone();
+    corrupt[24] += 1;
+    assert!(Vocab::from_bytes(&corrupt).is_err());
+}
+
+#[test]
+fn counter_must_be_past_every_id() {
+    // version 3 files don't hash the counter, so this file's hash is still right
+    let mut vocab = Vocab::new();
+    let a = vocab.intern(b"a").unwrap();
+    let b = vocab.intern(b"b").unwrap();
+    let mut bytes = to_bytes(&vocab);
+    bytes[8..12].copy_from_slice(&3u32.to_le_bytes());
+    set_u64(&mut bytes, 24, b.0);
+    let mut entries = Vec::new();
+    for (index, value) in [(a, b"a"), (b, b"b")] {
+        entries.extend_from_slice(&index.0.to_le_bytes());
+        entries.extend_from_slice(&1u64.to_le_bytes());
+        entries.extend_from_slice(value);
+    }
+    set_u64(&mut bytes, 32, fnv(&entries));
+    assert!(Vocab::from_bytes(&bytes).is_err());
+
+    let path = temp_path("counter");
+    std::fs::write(&path, &bytes).unwrap();
+    assert!(MappedVocab::open(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+
+    // with the counter put back, the same file loads
+    set_u64(&mut bytes, 24, b.0 + 1);
+    let mut loaded = Vocab::from_bytes(&bytes).unwrap();
+    assert_eq!(loaded.intern(b"c"), Some(VocabIndex(b.0 + 1)));
+}
@@ -1,36 +1,262 @@
+use std::collections::HashSet;
+use std::env;
+use std::fmt::Display;
+use std::io;
 use std::io::stdin;
-use conllu::io::Reader;
-use conllu::io::ReadSentence;
 use std::io::BufRead;
-use bumpalo::Bump;
-use std::cell::RefCell;
+use std::process;
+use yogurt::alignment::Alignment;
+use yogurt::alignment::TokenizationScore;
+use yogurt::calibration::Calibration;
+use yogurt::conllu;
+use yogurt::decoder::Decoder;
+use yogurt::decoder::Update;
+use yogurt::evaluation;
+use yogurt::evaluation::Evaluation;
+use yogurt::features::Clusters;
+use yogurt::features::Template;
+use yogurt::lexemizer::RuleSet;
 use yogurt::tokenizer;
+use yogurt::tokenizer::Hashing;
+use yogurt::tokenizer::PartOfSpeech;
+use yogurt::tokenizer::TagColumn;
+use yogurt::tokenizer::TagSet;
+use yogurt::tokenizer::Tagger;
+use yogurt::tokenizer::TrainOptions;
+use yogurt::universal::UniversalPos;
+use yogurt::vocab::Vocab;
 
-fn main() {
-    let mut arena = Bump::new();
-    let english_ruleset = tokenizer::RuleSet::english(&mut arena);
+const USAGE: &str = "usage: yogurt-utils (<train.conllu> [--upos] [--universal] [--align] [--epochs N] [--seed N] [--threads N] [--hash-bits N [--hash-seed N]] [--templates GROUP,...] [--clusters VOCAB] [--no-dictionary] [--beam N [--early-update] | --viterbi] [--save MODEL] | --model MODEL [--universal] [--beam N | --viterbi]) [--calibrate HELDOUT.conllu] [--evaluate GOLD.conllu [--known TRAIN.conllu] [--json]] [--top K]";
+
+struct Options {
+    train: Option<String>,
+    model: Option<String>,
+    save: Option<String>,
+    column: TagColumn,
+    universal: bool,
+    align: bool,
+    calibrate: Option<String>,
+    evaluate: Option<String>,
+    known: Option<String>,
+    json: bool,
+    top: usize,
+    decoder: Option<Decoder>, // overrides the one a loaded model was saved with
+    training: TrainOptions,
+}
 
-    for line in stdin().lock().lines() {
-        if let Ok(string) = line {
-            for tok in english_ruleset.tokenize(&string) {
-                dbg!(tok);
+// Train a tagger on a CoNLL-U treebank (or load a saved one), then tag lines from stdin
+// With --align the tagger is trained on our lexemes rather than the treebank's tokens
+// With --universal it tags Universal POS tags rather than Penn Treebank ones. Gold tags still
+// come from the column --upos picks, with Penn Treebank tags mapped to Universal ones
+fn main() {
+    let mut options = Options {
+        train: None,
+        model: None,
+        save: None,
+        column: TagColumn::Xpos,
+        universal: false,
+        align: false,
+        calibrate: None,
+        evaluate: None,
+        known: None,
+        json: false,
+        top: 0,
+        decoder: None,
+        training: TrainOptions::default(),
+    };
+    let mut hashing = Hashing { bits: 0, seed: 0 };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--upos" => options.column = TagColumn::Upos,
+            "--universal" => options.universal = true,
+            "--align" => options.align = true,
+            "--epochs" => options.training.epochs = number(args.next()),
+            "--seed" => options.training.seed = number(args.next()) as u64,
+            "--threads" => options.training.threads = number(args.next()).max(1),
+            "--hash-bits" => {
+                hashing.bits = number(args.next()).clamp(1, Hashing::MAX_BITS as usize) as u8
+            }
+            "--hash-seed" => hashing.seed = number(args.next()) as u64,
+            "--templates" => options.training.templates = templates(args.next()),
+            "--no-dictionary" => options.training.dictionary = None,
+            "--beam" => options.decoder = Some(Decoder::Beam(number(args.next()).max(1))),
+            "--viterbi" => options.decoder = Some(Decoder::Viterbi),
+            "--early-update" => options.training.update = Update::Early,
+            "--clusters" => {
+                let vocab = Vocab::load(args.next().unwrap_or_else(|| usage()));
+                let vocab = vocab.unwrap_or_else(|err| fail(err));
+                options.training.clusters = Some(Clusters::from_vocab(&vocab));
             }
+            "--calibrate" => options.calibrate = Some(args.next().unwrap_or_else(|| usage())),
+            "--evaluate" => options.evaluate = Some(args.next().unwrap_or_else(|| usage())),
+            "--known" => options.known = Some(args.next().unwrap_or_else(|| usage())),
+            "--json" => options.json = true,
+            "--top" => options.top = number(args.next()),
+            "--save" => options.save = Some(args.next().unwrap_or_else(|| usage())),
+            "--model" => options.model = Some(args.next().unwrap_or_else(|| usage())),
+            _ => options.train = Some(arg),
         }
     }
+
+    if hashing.bits != 0 {
+        options.training.hashing = Some(hashing);
+    }
+    if let Some(decoder) = options.decoder {
+        options.training.decoder = decoder;
+    }
+    if options.universal {
+        run::<UniversalPos>(&options);
+    } else {
+        run::<PartOfSpeech>(&options);
+    }
+}
+
+fn run<T: TagSet + Display>(options: &Options) {
+    let ruleset = RuleSet::english();
+    let tagger: io::Result<Tagger<T>> = match (&options.train, &options.model) {
+        (Some(path), None) => train(&ruleset, path, options),
+        (None, Some(path)) => Tagger::load(path),
+        _ => usage(),
+    };
+    let mut tagger = tagger.unwrap_or_else(|err| fail(err));
+    if let Some(decoder) = options.decoder {
+        check_decoder(decoder, tagger.templates());
+        tagger.set_decoder(decoder);
+    }
+    if let Some(path) = &options.save {
+        tagger.save(path).unwrap_or_else(|err| fail(err));
+    }
+    if let Some(path) = &options.calibrate {
+        calibrate(&tagger, path, options).unwrap_or_else(|err| fail(err));
+    }
+    if let Some(path) = &options.evaluate {
+        evaluate(&ruleset, &tagger, path, options).unwrap_or_else(|err| fail(err));
+        return;
+    }
+
+    for line in stdin().lock().lines().map_while(Result::ok) {
+        let tokens = tagger.tokenize_with_alternatives(ruleset.lexemize(line), options.top);
+        let tagged: Vec<String> = tokens
+            .iter()
+            .map(|token| {
+                let alternatives: Vec<String> = token
+                    .alternatives
+                    .iter()
+                    .map(|(tag, score)| format!("{}({:.2})", tag, score))
+                    .collect();
+                match options.top {
+                    0 => format!("{}/{}", token.lemma, token.part_of_speech),
+                    _ => format!("{}/{}", token.lemma, alternatives.join(",")),
+                }
+            })
+            .collect();
+        println!("{}", tagged.join(" "));
+    }
 }
 
-fn doread() {
-    let stdin = stdin();
-    let reader = Reader::new(stdin.lock());
-    for sentence_result in reader.sentences() {
-        if let Ok(sentence) = sentence_result {
-            println!("OK!!!");
-            println!("{}", sentence);
-        } else if let Err(err) = sentence_result {
-            println!("ERR!!!");
-            println!(" {} ", err);
-        } else {
-            println!("NANI!!!");
+fn train<T: TagSet>(ruleset: &RuleSet, path: &str, options: &Options) -> io::Result<Tagger<T>> {
+    check_decoder(options.training.decoder, &options.training.templates);
+    let treebank = conllu::read_file(path)?;
+    let sentences = if options.align {
+        let mut score = TokenizationScore::default();
+        for sentence in treebank.iter() {
+            let lexemes = ruleset.lexemize(sentence.text.clone());
+            score.add(Alignment::conllu(&lexemes, sentence).score());
         }
+        eprintln!(
+            "tokenization: precision {:.2}%, recall {:.2}%, f1 {:.2}%",
+            score.precision() * 100.0,
+            score.recall() * 100.0,
+            score.f1() * 100.0
+        );
+        tokenizer::aligned_sentences(ruleset, &treebank, options.column)?
+    } else {
+        tokenizer::tagged_sentences(&treebank, options.column)?
+    };
+
+    Tagger::train_with_progress(&sentences, &options.training, |progress| {
+        eprintln!(
+            "epoch {}/{}: {} tokens, {:.2}% correct",
+            progress.epoch,
+            progress.epochs,
+            progress.tokens,
+            progress.accuracy() * 100.0
+        );
+    })
+}
+
+// Template groups, e.g. "words,affixes,tags" (see Template::group)
+fn templates(arg: Option<String>) -> Vec<Template> {
+    let arg = arg.unwrap_or_else(|| usage());
+    let groups: Option<Vec<&[Template]>> = arg.split(',').map(Template::group).collect();
+    Template::groups(&groups.unwrap_or_else(|| usage()))
+}
+
+// Print how the tagger's scores match its accuracy on a held out treebank
+fn calibrate<T: TagSet>(tagger: &Tagger<T>, path: &str, options: &Options) -> io::Result<()> {
+    let sentences = tokenizer::tagged_sentences(&conllu::read_file(path)?, options.column)?;
+    let calibration = Calibration::measure(tagger, &sentences, 10);
+    let bins = calibration.bins.len();
+    for (i, bin) in calibration.bins.iter().enumerate() {
+        eprintln!(
+            "score {:.1}-{:.1}: {} tags, mean score {:.3}, accuracy {:.3}",
+            i as f64 / bins as f64,
+            (i + 1) as f64 / bins as f64,
+            bin.tags,
+            bin.mean_score(),
+            bin.accuracy()
+        );
     }
+    eprintln!(
+        "accuracy {:.2}%, mean score {:.2}%, expected calibration error {:.4}",
+        calibration.accuracy() * 100.0,
+        calibration.mean_score() * 100.0,
+        calibration.expected_error()
+    );
+    Ok(())
+}
+
+// Print how well the tagger does on a gold treebank, then stop
+// Words are known if they are in --known, or else the training treebank
+fn evaluate<T: TagSet>(
+    ruleset: &RuleSet,
+    tagger: &Tagger<T>,
+    path: &str,
+    options: &Options,
+) -> io::Result<()> {
+    let known = match options.known.as_ref().or(options.train.as_ref()) {
+        Some(path) => evaluation::known_words(&conllu::read_file(path)?),
+        None => HashSet::new(),
+    };
+    let gold = conllu::read_file(path)?;
+    let evaluation = Evaluation::evaluate(tagger, ruleset, &gold, options.column, &known)?;
+    if options.json {
+        println!("{}", evaluation.json());
+    } else {
+        print!("{}", evaluation.text());
+    }
+    Ok(())
+}
+
+fn check_decoder(decoder: Decoder, templates: &[Template]) {
+    if decoder == Decoder::Viterbi && templates.iter().any(|t| t.history() > 1) {
+        eprintln!("--viterbi needs templates that only look at the previous tag, e.g. --templates words,affixes");
+        process::exit(2);
+    }
+}
+
+fn number(arg: Option<String>) -> usize {
+    arg.and_then(|arg| arg.parse().ok())
+        .unwrap_or_else(|| usage())
+}
+
+fn fail(err: io::Error) -> ! {
+    eprintln!("{}", err);
+    process::exit(1);
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
 }